@@ -0,0 +1,123 @@
+use anchor_client::ClientError as AnchorClientError;
+use raydium_amm_v3::error::ErrorCode;
+use solana_client::{
+    client_error::ClientError as RpcClientError, nonblocking::pubsub_client::PubsubClientError,
+};
+use solana_sdk::{
+    instruction::InstructionError,
+    message::CompileError,
+    native_token::LAMPORTS_PER_SOL,
+    program_error::ProgramError,
+    pubkey::{ParsePubkeyError, Pubkey},
+    signature::SignerError,
+    transaction::TransactionError,
+};
+use std::num::TryFromIntError;
+use thiserror::Error;
+
+use super::instructions::events_instructions_parse::decode_program_error;
+
+/// Errors returned by the public functions of the client. Errors without a dedicated variant
+/// are carried by `Other`.
+#[derive(Debug, Error)]
+pub enum ClientError {
+    #[error("invalid client config: {0:#}")]
+    ConfigError(#[source] anyhow::Error),
+    #[error("rpc request failed: {0}")]
+    RpcError(#[source] Box<RpcClientError>),
+    #[error("failed to deserialize account: {0:#}")]
+    DeserializeError(#[source] anyhow::Error),
+    #[error(
+        "insufficient liquidity: only {available} of the requested {requested} can be swapped out"
+    )]
+    InsufficientLiquidity { requested: u64, available: u64 },
+    #[error("{0} is disabled on this pool")]
+    PoolPaused(&'static str),
+    #[error(
+        "insufficient SOL: needs ~{:.6} SOL, {payer} has {:.6}",
+        sol(.needed),
+        sol(.available)
+    )]
+    InsufficientSol {
+        payer: Pubkey,
+        needed: u64,
+        available: u64,
+    },
+    /// The transaction failed on the program's slippage checks, it may succeed again with a wider
+    /// slippage.
+    #[error("slippage exceeded: {0}")]
+    SlippageExceeded(ErrorCode),
+    /// The transaction failed on an error of the program other than the slippage checks.
+    #[error("program error {code}: {message}")]
+    ProgramError { code: u32, message: &'static str },
+    #[error(transparent)]
+    Other(#[from] anyhow::Error),
+}
+
+impl From<RpcClientError> for ClientError {
+    fn from(err: RpcClientError) -> Self {
+        let Some(transaction_error) = err.get_transaction_error() else {
+            return ClientError::RpcError(Box::new(err));
+        };
+        if let Some(slippage_error) = slippage_error(&transaction_error) {
+            return ClientError::SlippageExceeded(slippage_error);
+        }
+        match program_error(&transaction_error) {
+            Some(program_error) => program_error,
+            None => ClientError::RpcError(Box::new(err)),
+        }
+    }
+}
+
+macro_rules! impl_from_other {
+    ($($error:ty),* $(,)?) => {
+        $(
+            impl From<$error> for ClientError {
+                fn from(err: $error) -> Self {
+                    ClientError::Other(err.into())
+                }
+            }
+        )*
+    };
+}
+
+impl_from_other!(
+    AnchorClientError,
+    bincode::Error,
+    CompileError,
+    ParsePubkeyError,
+    ProgramError,
+    PubsubClientError,
+    serde_json::Error,
+    SignerError,
+    TryFromIntError,
+);
+
+fn sol(lamports: &u64) -> f64 {
+    *lamports as f64 / LAMPORTS_PER_SOL as f64
+}
+
+/// The slippage check of the program that failed the transaction, if any.
+pub(crate) fn slippage_error(err: &TransactionError) -> Option<ErrorCode> {
+    let TransactionError::InstructionError(_, InstructionError::Custom(code)) = err else {
+        return None;
+    };
+    [
+        ErrorCode::PriceSlippageCheck,
+        ErrorCode::TooLittleOutputReceived,
+        ErrorCode::TooMuchInputPaid,
+    ]
+    .into_iter()
+    .find(|slippage_error| u32::from(*slippage_error) == *code)
+}
+
+/// The error of the program other than the slippage checks that failed the transaction, if any.
+pub(crate) fn program_error(err: &TransactionError) -> Option<ClientError> {
+    let TransactionError::InstructionError(_, InstructionError::Custom(code)) = err else {
+        return None;
+    };
+    decode_program_error(*code).map(|message| ClientError::ProgramError {
+        code: *code,
+        message,
+    })
+}
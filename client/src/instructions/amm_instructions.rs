@@ -1,82 +1,246 @@
 use anchor_client::{Client, Cluster};
 use anchor_lang::prelude::AccountMeta;
-use anyhow::Result;
+use anchor_lang::{AccountDeserialize, Discriminator, InstructionData, ToAccountMetas};
+use anyhow::{anyhow, Result};
 use mpl_token_metadata::accounts::Metadata;
+use solana_client::rpc_client::RpcClient;
 use solana_sdk::{
-    instruction::Instruction, pubkey::Pubkey, signature::Signer, system_program, sysvar,
+    address_lookup_table::{state::AddressLookupTable, AddressLookupTableAccount},
+    clock::Clock,
+    instruction::Instruction,
+    message::{v0, Message, VersionedMessage},
+    pubkey::Pubkey,
+    signature::{Keypair, Signer},
+    system_instruction, system_program, sysvar,
+    transaction::VersionedTransaction,
 };
+use spl_associated_token_account::get_associated_token_address_with_program_id;
+use spl_token_2022::{extension::StateWithExtensions, state::Mint};
 
 use raydium_amm_v3::accounts as raydium_accounts;
 use raydium_amm_v3::instruction as raydium_instruction;
+use raydium_amm_v3::libraries::liquidity_math;
 use raydium_amm_v3::states::{
-    AMM_CONFIG_SEED, OBSERVATION_SEED, OPERATION_SEED, POOL_SEED, POOL_VAULT_SEED, POSITION_SEED,
-    TICK_ARRAY_SEED,
+    reward_period_limit, PersonalPositionState, PoolState, RewardInfo, TickArrayBitmapExtension,
+    TickArrayState, AMM_CONFIG_SEED, FEE_RATE_DENOMINATOR_VALUE, OBSERVATION_SEED, OPERATION_SEED,
+    POOL_REWARD_VAULT_SEED, POOL_SEED, POOL_TICK_ARRAY_BITMAP_SEED, POOL_VAULT_SEED, POSITION_SEED,
+    REWARD_NUM, TICK_ARRAY_SEED,
 };
+use std::collections::HashMap;
 use std::rc::Rc;
 
-use super::super::{read_keypair_file, ClientConfig};
+use super::super::{
+    amount_with_slippage, amounts_to_liquidity, assert_usable_token_account_data,
+    compute_fees_owed, compute_rewards_owed, deserialize_anchor_account, get_sqrt_price_limit_x64,
+    get_tick_array_key, get_transfer_inverse_fee, min_amount_out_with_slippage, mint_token_program,
+    order_tick_arrays_for_swap, read_keypair_file, resolve_token_programs, validate_tick_range,
+    ClientConfig, ClientError, PoolStatusFlags, PositionNftTokenInfo,
+};
+use super::rpc::{
+    ensure_sufficient_sol, estimate_rent_for_create_pool, estimate_rent_for_open_position,
+    estimate_rent_for_tick_arrays, estimate_tx_size, get_multiple_accounts_chunked,
+    MAX_COMPUTE_UNIT_LIMIT,
+};
+use super::token_instructions::{apply_transfer_fee, create_ata_idempotent_instr};
+use super::utils::builder_trace;
+
+/// Max size of a serialized transaction, equals to `solana_packet::PACKET_DATA_SIZE`.
+pub const MAX_TRANSACTION_SIZE: usize = 1232;
+
+/// Check fee rates against the bounds `create_amm_config` asserts on-chain, all in units of
+/// `FEE_RATE_DENOMINATOR_VALUE`: the trade fee must be below it, and the protocol and fund
+/// shares of the trade fee can't add up to more than it.
+fn validate_fee_rates(
+    trade_fee_rate: u32,
+    protocol_fee_rate: u32,
+    fund_fee_rate: u32,
+) -> Result<()> {
+    if trade_fee_rate >= FEE_RATE_DENOMINATOR_VALUE {
+        return Err(anyhow!(
+            "trade fee rate {} must be below {}",
+            trade_fee_rate,
+            FEE_RATE_DENOMINATOR_VALUE
+        ));
+    }
+    if u64::from(protocol_fee_rate) + u64::from(fund_fee_rate)
+        > u64::from(FEE_RATE_DENOMINATOR_VALUE)
+    {
+        return Err(anyhow!(
+            "protocol fee rate {} and fund fee rate {} add up to more than {}",
+            protocol_fee_rate,
+            fund_fee_rate,
+            FEE_RATE_DENOMINATOR_VALUE
+        ));
+    }
+    Ok(())
+}
 
+/// Build `create_amm_config`, creating the fee tier `index` at the config PDA derived from
+/// `AMM_CONFIG_SEED` and the index. Only the admin can create configs, `admin` pays for the
+/// account.
 pub fn create_amm_config_instr(
-    config: &ClientConfig,
-    config_index: u16,
+    admin: &Pubkey,
+    index: u16,
     tick_spacing: u16,
     trade_fee_rate: u32,
     protocol_fee_rate: u32,
     fund_fee_rate: u32,
-) -> Result<Vec<Instruction>> {
-    let payer = read_keypair_file(&config.admin_path)?;
-    let url = Cluster::Custom(config.http_url.clone(), config.ws_url.clone());
-    // Client.
-    let client = Client::new(url, Rc::new(payer));
-    let program = client.program(config.raydium_v3_program)?;
+    program_id: &Pubkey,
+) -> Result<Vec<Instruction>, ClientError> {
+    if *admin != raydium_amm_v3::admin::ID {
+        return Err(anyhow!(
+            "{} can't create amm configs, only the admin {} can",
+            admin,
+            raydium_amm_v3::admin::ID
+        )
+        .into());
+    }
+    if tick_spacing == 0 {
+        return Err(anyhow!("tick_spacing must be positive").into());
+    }
+    validate_fee_rates(trade_fee_rate, protocol_fee_rate, fund_fee_rate)?;
     let (amm_config_key, __bump) = Pubkey::find_program_address(
-        &[AMM_CONFIG_SEED.as_bytes(), &config_index.to_be_bytes()],
-        &program.id(),
+        &[AMM_CONFIG_SEED.as_bytes(), &index.to_be_bytes()],
+        program_id,
     );
-    let instructions = program
-        .request()
-        .accounts(raydium_accounts::CreateAmmConfig {
-            owner: program.payer(),
+    builder_trace!(
+        "create_amm_config: config {}, index {}, tick_spacing {}, trade_fee_rate {}",
+        amm_config_key,
+        index,
+        tick_spacing,
+        trade_fee_rate
+    );
+    Ok(vec![Instruction {
+        program_id: *program_id,
+        accounts: raydium_accounts::CreateAmmConfig {
+            owner: *admin,
             amm_config: amm_config_key,
             system_program: system_program::id(),
-        })
-        .args(raydium_instruction::CreateAmmConfig {
-            index: config_index,
+        }
+        .to_account_metas(None),
+        data: raydium_instruction::CreateAmmConfig {
+            index,
             tick_spacing,
             trade_fee_rate,
             protocol_fee_rate,
             fund_fee_rate,
-        })
-        .instructions()?;
-    Ok(instructions)
+        }
+        .data(),
+    }])
+}
+
+/// A setting of an amm config changed by `update_amm_config`. Fee rates are in units of
+/// `FEE_RATE_DENOMINATOR_VALUE`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum AmmConfigParam {
+    TradeFeeRate(u32),
+    ProtocolFeeRate(u32),
+    FundFeeRate(u32),
+    NewOwner(Pubkey),
+    FundOwner(Pubkey),
 }
 
+impl AmmConfigParam {
+    /// The `param` index `update_amm_config` dispatches on.
+    pub fn index(&self) -> u8 {
+        match self {
+            AmmConfigParam::TradeFeeRate(_) => 0,
+            AmmConfigParam::ProtocolFeeRate(_) => 1,
+            AmmConfigParam::FundFeeRate(_) => 2,
+            AmmConfigParam::NewOwner(_) => 3,
+            AmmConfigParam::FundOwner(_) => 4,
+        }
+    }
+
+    /// The `value` argument of the instruction, unused by the owner updates which pass the new
+    /// owner as the first remaining account instead.
+    fn value(&self) -> u32 {
+        match self {
+            AmmConfigParam::TradeFeeRate(rate)
+            | AmmConfigParam::ProtocolFeeRate(rate)
+            | AmmConfigParam::FundFeeRate(rate) => *rate,
+            AmmConfigParam::NewOwner(_) | AmmConfigParam::FundOwner(_) => 0,
+        }
+    }
+
+    fn remaining_accounts(&self) -> Vec<AccountMeta> {
+        match self {
+            AmmConfigParam::NewOwner(owner) | AmmConfigParam::FundOwner(owner) => {
+                vec![AccountMeta::new_readonly(*owner, false)]
+            }
+            _ => Vec::new(),
+        }
+    }
+}
+
+/// Build `update_amm_config` changing `param` on the config of `config_index`. Fee rates are
+/// checked against their individual bounds only, the program additionally rejects protocol and
+/// fund fee rates adding up to more than `FEE_RATE_DENOMINATOR_VALUE` with the current config.
 pub fn update_amm_config_instr(
-    config: &ClientConfig,
-    amm_config: Pubkey,
-    remaining_accounts: Vec<AccountMeta>,
-    param: u8,
-    value: u32,
-) -> Result<Vec<Instruction>> {
-    let payer = read_keypair_file(&config.payer_path)?;
-    let admin = read_keypair_file(&config.admin_path)?;
-    let url = Cluster::Custom(config.http_url.clone(), config.ws_url.clone());
-    // Client.
-    let client = Client::new(url, Rc::new(payer));
-    let program = client.program(config.raydium_v3_program)?;
-    let instructions = program
-        .request()
-        .accounts(raydium_accounts::UpdateAmmConfig {
-            owner: admin.pubkey(),
-            amm_config,
-        })
-        .accounts(remaining_accounts)
-        .args(raydium_instruction::UpdateAmmConfig { param, value })
-        .instructions()?;
-    Ok(instructions)
+    admin: &Pubkey,
+    config_index: u16,
+    param: AmmConfigParam,
+    program_id: &Pubkey,
+) -> Result<Vec<Instruction>, ClientError> {
+    if *admin != raydium_amm_v3::admin::ID {
+        return Err(anyhow!(
+            "{} can't update amm configs, only the admin {} can",
+            admin,
+            raydium_amm_v3::admin::ID
+        )
+        .into());
+    }
+    match param {
+        AmmConfigParam::TradeFeeRate(rate) if rate >= FEE_RATE_DENOMINATOR_VALUE => {
+            return Err(anyhow!(
+                "trade fee rate {} must be below {}",
+                rate,
+                FEE_RATE_DENOMINATOR_VALUE
+            )
+            .into());
+        }
+        AmmConfigParam::ProtocolFeeRate(rate) | AmmConfigParam::FundFeeRate(rate)
+            if rate > FEE_RATE_DENOMINATOR_VALUE =>
+        {
+            return Err(anyhow!(
+                "fee rate {} must not exceed {}",
+                rate,
+                FEE_RATE_DENOMINATOR_VALUE
+            )
+            .into());
+        }
+        _ => {}
+    }
+    let (amm_config_key, __bump) = Pubkey::find_program_address(
+        &[AMM_CONFIG_SEED.as_bytes(), &config_index.to_be_bytes()],
+        program_id,
+    );
+    builder_trace!(
+        "update_amm_config: config {}, index {}, {:?}",
+        amm_config_key,
+        config_index,
+        param
+    );
+    let mut accounts = raydium_accounts::UpdateAmmConfig {
+        owner: *admin,
+        amm_config: amm_config_key,
+    }
+    .to_account_metas(None);
+    accounts.extend(param.remaining_accounts());
+    Ok(vec![Instruction {
+        program_id: *program_id,
+        accounts,
+        data: raydium_instruction::UpdateAmmConfig {
+            param: param.index(),
+            value: param.value(),
+        }
+        .data(),
+    }])
 }
 
-pub fn create_operation_account_instr(config: &ClientConfig) -> Result<Vec<Instruction>> {
+pub fn create_operation_account_instr(
+    config: &ClientConfig,
+) -> Result<Vec<Instruction>, ClientError> {
     let payer = read_keypair_file(&config.admin_path)?;
     let url = Cluster::Custom(config.http_url.clone(), config.ws_url.clone());
     // Client.
@@ -100,7 +264,7 @@ pub fn update_operation_account_instr(
     config: &ClientConfig,
     param: u8,
     keys: Vec<Pubkey>,
-) -> Result<Vec<Instruction>> {
+) -> Result<Vec<Instruction>, ClientError> {
     let payer = read_keypair_file(&config.admin_path)?;
     let url = Cluster::Custom(config.http_url.clone(), config.ws_url.clone());
     // Client.
@@ -120,7 +284,12 @@ pub fn update_operation_account_instr(
     Ok(instructions)
 }
 
+/// Build `create_pool`. `open_time` is the unix timestamp from which swaps are allowed and
+/// defaults to 0, i.e. open immediately. The program requires it to be before the current
+/// block time, so future launches can't be scheduled at creation and are rejected here. Fails
+/// with `ClientError::InsufficientSol` when the payer can't pay the rent of the pool accounts.
 pub fn create_pool_instr(
+    rpc_client: &RpcClient,
     config: &ClientConfig,
     amm_config: Pubkey,
     token_mint_0: Pubkey,
@@ -129,9 +298,28 @@ pub fn create_pool_instr(
     token_program_1: Pubkey,
     tick_array_bitmap: Pubkey,
     sqrt_price_x64: u128,
-    open_time: u64,
-) -> Result<Vec<Instruction>> {
+    open_time: Option<u64>,
+) -> Result<Vec<Instruction>, ClientError> {
+    let open_time = open_time.unwrap_or(0);
+    if open_time > 0 {
+        let clock: Clock =
+            bincode::deserialize(&rpc_client.get_account(&sysvar::clock::id())?.data)?;
+        let block_timestamp = clock.unix_timestamp as u64;
+        if open_time >= block_timestamp {
+            return Err(anyhow!(
+                "open_time {} must be before the current block time {}",
+                open_time,
+                block_timestamp
+            )
+            .into());
+        }
+    }
     let payer = read_keypair_file(&config.payer_path)?;
+    ensure_sufficient_sol(
+        rpc_client,
+        &payer.pubkey(),
+        estimate_rent_for_create_pool(rpc_client, &token_mint_0, &token_mint_1)?,
+    )?;
     let url = Cluster::Custom(config.http_url.clone(), config.ws_url.clone());
     // Client.
     let client = Client::new(url, Rc::new(payer));
@@ -168,6 +356,15 @@ pub fn create_pool_instr(
         ],
         &program.id(),
     );
+    builder_trace!(
+        "create_pool: pool {}, vault_0 {}, vault_1 {}, observation {}, sqrt_price_x64 {}, open_time {}",
+        pool_account_key,
+        token_vault_0,
+        token_vault_1,
+        observation_key,
+        sqrt_price_x64,
+        open_time
+    );
     let instructions = program
         .request()
         .accounts(raydium_accounts::CreatePool {
@@ -193,6 +390,9 @@ pub fn create_pool_instr(
     Ok(instructions)
 }
 
+/// Open a position with `open_position_v2`. The tick arrays and the position PDAs are derived
+/// from the tick bounds and the NFT mint. `with_metadata` also creates the Metaplex metadata
+/// account of the position NFT, so that it shows up in NFT-aware wallets.
 pub fn open_position_instr(
     config: &ClientConfig,
     pool_account_key: Pubkey,
@@ -213,12 +413,14 @@ pub fn open_position_instr(
     tick_array_lower_start_index: i32,
     tick_array_upper_start_index: i32,
     with_metadata: bool,
-) -> Result<Vec<Instruction>> {
+) -> Result<Vec<Instruction>, ClientError> {
     let payer = read_keypair_file(&config.payer_path)?;
     let url = Cluster::Custom(config.http_url.clone(), config.ws_url.clone());
     // Client.
     let client = Client::new(url, Rc::new(payer));
     let program = client.program(config.raydium_v3_program)?;
+    let pool_state: PoolState = program.account(pool_account_key)?;
+    validate_tick_range(tick_lower_index, tick_upper_index, pool_state.tick_spacing)?;
     let nft_ata_token_account =
         spl_associated_token_account::get_associated_token_address(&program.payer(), &nft_mint_key);
     let (metadata_account_key, _bump) = Pubkey::find_program_address(
@@ -258,6 +460,19 @@ pub fn open_position_instr(
         &[POSITION_SEED.as_bytes(), nft_mint_key.to_bytes().as_ref()],
         &program.id(),
     );
+    builder_trace!(
+        "open_position: pool {}, personal_position {}, protocol_position {}, ticks [{}, {}], tick_arrays [{}, {}], liquidity {}, amount_0_max {}, amount_1_max {}",
+        pool_account_key,
+        personal_position_key,
+        protocol_position_key,
+        tick_lower_index,
+        tick_upper_index,
+        tick_array_lower,
+        tick_array_upper,
+        liquidity,
+        amount_0_max,
+        amount_1_max
+    );
     let instructions = program
         .request()
         .accounts(raydium_accounts::OpenPositionV2 {
@@ -320,12 +535,14 @@ pub fn open_position_with_token22_nft_instr(
     tick_array_lower_start_index: i32,
     tick_array_upper_start_index: i32,
     with_metadata: bool,
-) -> Result<Vec<Instruction>> {
+) -> Result<Vec<Instruction>, ClientError> {
     let payer = read_keypair_file(&config.payer_path)?;
     let url = Cluster::Custom(config.http_url.clone(), config.ws_url.clone());
     // Client.
     let client = Client::new(url, Rc::new(payer));
     let program = client.program(config.raydium_v3_program)?;
+    let pool_state: PoolState = program.account(pool_account_key)?;
+    validate_tick_range(tick_lower_index, tick_upper_index, pool_state.tick_spacing)?;
     let nft_ata_token_account =
         spl_associated_token_account::get_associated_token_address_with_program_id(
             &program.payer(),
@@ -420,12 +637,14 @@ pub fn increase_liquidity_instr(
     tick_upper_index: i32,
     tick_array_lower_start_index: i32,
     tick_array_upper_start_index: i32,
-) -> Result<Vec<Instruction>> {
+) -> Result<Vec<Instruction>, ClientError> {
     let payer = read_keypair_file(&config.payer_path)?;
     let url = Cluster::Custom(config.http_url.clone(), config.ws_url.clone());
     // Client.
     let client = Client::new(url, Rc::new(payer));
     let program = client.program(config.raydium_v3_program)?;
+    let pool_state: PoolState = program.account(pool_account_key)?;
+    validate_tick_range(tick_lower_index, tick_upper_index, pool_state.tick_spacing)?;
     let (tick_array_lower, __bump) = Pubkey::find_program_address(
         &[
             TICK_ARRAY_SEED.as_bytes(),
@@ -456,6 +675,17 @@ pub fn increase_liquidity_instr(
         &program.id(),
     );
 
+    builder_trace!(
+        "increase_liquidity: pool {}, personal_position {}, protocol_position {}, tick_arrays [{}, {}], liquidity {}, amount_0_max {}, amount_1_max {}",
+        pool_account_key,
+        personal_position_key,
+        protocol_position_key,
+        tick_array_lower,
+        tick_array_upper,
+        liquidity,
+        amount_0_max,
+        amount_1_max
+    );
     let instructions = program
         .request()
         .accounts(raydium_accounts::IncreaseLiquidityV2 {
@@ -505,12 +735,14 @@ pub fn decrease_liquidity_instr(
     tick_upper_index: i32,
     tick_array_lower_start_index: i32,
     tick_array_upper_start_index: i32,
-) -> Result<Vec<Instruction>> {
+) -> Result<Vec<Instruction>, ClientError> {
     let payer = read_keypair_file(&config.payer_path)?;
     let url = Cluster::Custom(config.http_url.clone(), config.ws_url.clone());
     // Client.
     let client = Client::new(url, Rc::new(payer));
     let program = client.program(config.raydium_v3_program)?;
+    let pool_state: PoolState = program.account(pool_account_key)?;
+    validate_tick_range(tick_lower_index, tick_upper_index, pool_state.tick_spacing)?;
     let (personal_position_key, __bump) = Pubkey::find_program_address(
         &[POSITION_SEED.as_bytes(), nft_mint_key.to_bytes().as_ref()],
         &program.id(),
@@ -540,6 +772,17 @@ pub fn decrease_liquidity_instr(
         ],
         &program.id(),
     );
+    builder_trace!(
+        "decrease_liquidity: pool {}, personal_position {}, protocol_position {}, tick_arrays [{}, {}], liquidity {}, amount_0_min {}, amount_1_min {}",
+        pool_account_key,
+        personal_position_key,
+        protocol_position_key,
+        tick_array_lower,
+        tick_array_upper,
+        liquidity,
+        amount_0_min,
+        amount_1_min
+    );
     let instructions = program
         .request()
         .accounts(raydium_accounts::DecreaseLiquidityV2 {
@@ -575,7 +818,7 @@ pub fn close_personal_position_instr(
     nft_mint_key: Pubkey,
     nft_token_key: Pubkey,
     nft_token_program: Pubkey,
-) -> Result<Vec<Instruction>> {
+) -> Result<Vec<Instruction>, ClientError> {
     let payer = read_keypair_file(&config.payer_path)?;
     let url = Cluster::Custom(config.http_url.clone(), config.ws_url.clone());
     // Client.
@@ -600,6 +843,48 @@ pub fn close_personal_position_instr(
     Ok(instructions)
 }
 
+/// Build the instruction closing a position: burns the position nft and closes the personal
+/// position account, returning the rent to `owner`. The program rejects closing a position
+/// with liquidity left, so `liquidity` must be the current liquidity of the position.
+/// The nft is burnt with `nft.program`, the token program holding it, either SPL Token or
+/// Token-2022 for positions opened with `open_position_with_token22_nft`.
+pub fn close_position_instr(
+    owner: &Pubkey,
+    nft: &PositionNftTokenInfo,
+    liquidity: u128,
+    program_id: &Pubkey,
+) -> Result<Vec<Instruction>, ClientError> {
+    if liquidity != 0 {
+        return Err(anyhow!(
+            "position {} still has liquidity {}, decrease it to zero before closing",
+            nft.position,
+            liquidity
+        )
+        .into());
+    }
+    if nft.program != spl_token::id() && nft.program != spl_token_2022::id() {
+        return Err(anyhow!(
+            "position nft account {} is held by {}, not a token program",
+            nft.key,
+            nft.program
+        )
+        .into());
+    }
+    Ok(vec![Instruction {
+        program_id: *program_id,
+        accounts: raydium_accounts::ClosePosition {
+            nft_owner: *owner,
+            position_nft_mint: nft.mint,
+            position_nft_account: nft.key,
+            personal_position: nft.position,
+            system_program: system_program::id(),
+            token_program: nft.program,
+        }
+        .to_account_metas(None),
+        data: raydium_instruction::ClosePosition.data(),
+    }])
+}
+
 pub fn swap_instr(
     config: &ClientConfig,
     amm_config: Pubkey,
@@ -615,12 +900,31 @@ pub fn swap_instr(
     other_amount_threshold: u64,
     sqrt_price_limit_x64: Option<u128>,
     is_base_input: bool,
-) -> Result<Vec<Instruction>> {
+) -> Result<Vec<Instruction>, ClientError> {
     let payer = read_keypair_file(&config.payer_path)?;
     let url = Cluster::Custom(config.http_url.clone(), config.ws_url.clone());
     // Client.
     let client = Client::new(url, Rc::new(payer));
     let program = client.program(config.raydium_v3_program)?;
+    if sqrt_price_limit_x64.is_some() {
+        let pool_state: PoolState = program.account(pool_account_key)?;
+        get_sqrt_price_limit_x64(
+            sqrt_price_limit_x64,
+            input_vault == pool_state.token_vault_0,
+            pool_state.sqrt_price_x64,
+        )?;
+    }
+    builder_trace!(
+        "swap: pool {}, input_vault {}, output_vault {}, tick_array {}, remaining_accounts {:?}, amount {}, other_amount_threshold {}, is_base_input {}",
+        pool_account_key,
+        input_vault,
+        output_vault,
+        tick_array,
+        remaining_accounts.iter().map(|a| a.pubkey).collect::<Vec<_>>(),
+        amount,
+        other_amount_threshold,
+        is_base_input
+    );
     let instructions = program
         .request()
         .accounts(raydium_accounts::SwapSingle {
@@ -639,6 +943,7 @@ pub fn swap_instr(
         .args(raydium_instruction::Swap {
             amount,
             other_amount_threshold,
+            // Zero lets the program default to the min/max sqrt price and reject partial fills
             sqrt_price_limit_x64: sqrt_price_limit_x64.unwrap_or(0u128),
             is_base_input,
         })
@@ -662,12 +967,30 @@ pub fn swap_v2_instr(
     other_amount_threshold: u64,
     sqrt_price_limit_x64: Option<u128>,
     is_base_input: bool,
-) -> Result<Vec<Instruction>> {
+) -> Result<Vec<Instruction>, ClientError> {
     let payer = read_keypair_file(&config.payer_path)?;
     let url = Cluster::Custom(config.http_url.clone(), config.ws_url.clone());
     // Client.
     let client = Client::new(url, Rc::new(payer));
     let program = client.program(config.raydium_v3_program)?;
+    if sqrt_price_limit_x64.is_some() {
+        let pool_state: PoolState = program.account(pool_account_key)?;
+        get_sqrt_price_limit_x64(
+            sqrt_price_limit_x64,
+            input_vault == pool_state.token_vault_0,
+            pool_state.sqrt_price_x64,
+        )?;
+    }
+    builder_trace!(
+        "swap_v2: pool {}, input_vault {}, output_vault {}, remaining_accounts {:?}, amount {}, other_amount_threshold {}, is_base_input {}",
+        pool_account_key,
+        input_vault,
+        output_vault,
+        remaining_accounts.iter().map(|a| a.pubkey).collect::<Vec<_>>(),
+        amount,
+        other_amount_threshold,
+        is_base_input
+    );
     let instructions = program
         .request()
         .accounts(raydium_accounts::SwapSingleV2 {
@@ -689,6 +1012,7 @@ pub fn swap_v2_instr(
         .args(raydium_instruction::SwapV2 {
             amount,
             other_amount_threshold,
+            // Zero lets the program default to the min/max sqrt price and reject partial fills
             sqrt_price_limit_x64: sqrt_price_limit_x64.unwrap_or(0u128),
             is_base_input,
         })
@@ -696,19 +1020,460 @@ pub fn swap_v2_instr(
     Ok(instructions)
 }
 
+/// Build a swap on the given pool, using `swap_v2` whenever either mint of the pool is a
+/// Token-2022 mint and the legacy `swap` otherwise. The tick array bitmap extension is passed
+/// ahead of `tick_array_keys` in the remaining accounts, as the program expects, and the tick
+/// arrays are passed in swap order whatever the order of `tick_array_keys`, see
+/// `order_tick_arrays_for_swap`. Ordering them requires fetching every tick array, about 10KB
+/// each, along with the mints and the bitmap extension in one batched request.
+/// `quoted_other_amount` is the quoted output for a base input swap, or the quoted input
+/// otherwise, and `config.slippage` is applied to it to bound the execution price.
+pub fn build_swap_instr(
+    rpc_client: &RpcClient,
+    config: &ClientConfig,
+    pool_account_key: Pubkey,
+    pool_state: &PoolState,
+    user_input_token: Pubkey,
+    user_output_token: Pubkey,
+    zero_for_one: bool,
+    tick_array_keys: Vec<Pubkey>,
+    amount: u64,
+    quoted_other_amount: u64,
+    sqrt_price_limit_x64: Option<u128>,
+    is_base_input: bool,
+) -> Result<Vec<Instruction>, ClientError> {
+    PoolStatusFlags::from_pool(pool_state).ensure_swap_enabled()?;
+    if tick_array_keys.is_empty() {
+        return Err(anyhow!("at least one tick array is required to swap").into());
+    }
+    let (input_vault, output_vault, input_vault_mint, output_vault_mint) = if zero_for_one {
+        (
+            pool_state.token_vault_0,
+            pool_state.token_vault_1,
+            pool_state.token_mint_0,
+            pool_state.token_mint_1,
+        )
+    } else {
+        (
+            pool_state.token_vault_1,
+            pool_state.token_vault_0,
+            pool_state.token_mint_1,
+            pool_state.token_mint_0,
+        )
+    };
+    let other_amount_threshold = if is_base_input {
+        min_amount_out_with_slippage(quoted_other_amount, config.slippage)
+    } else {
+        amount_with_slippage(quoted_other_amount, config.slippage, true)
+    };
+    let (tickarray_bitmap_extension, __bump) = Pubkey::find_program_address(
+        &[
+            POOL_TICK_ARRAY_BITMAP_SEED.as_bytes(),
+            pool_account_key.to_bytes().as_ref(),
+        ],
+        &config.raydium_v3_program,
+    );
+    let mut keys = vec![
+        pool_state.token_mint_0,
+        pool_state.token_mint_1,
+        user_input_token,
+        tickarray_bitmap_extension,
+    ];
+    keys.extend(&tick_array_keys);
+    let mut accounts = get_multiple_accounts_chunked(rpc_client, &keys)?;
+    let tick_array_accounts = accounts.split_off(4);
+    let bitmap_extension = match accounts.pop().flatten() {
+        Some(account) => Some(deserialize_anchor_account::<TickArrayBitmapExtension>(
+            &account,
+        )?),
+        None => None,
+    };
+    // a missing input account may still be created ahead of the swap
+    if let Some(input_account) = accounts.pop().flatten() {
+        assert_usable_token_account_data(&user_input_token, &input_account.data)?;
+    }
+    let mut tick_arrays = Vec::with_capacity(tick_array_keys.len());
+    for (key, account) in tick_array_keys.iter().zip(tick_array_accounts) {
+        let account = account.ok_or_else(|| anyhow!("tick array {} not found", key))?;
+        let tick_array = deserialize_anchor_account::<TickArrayState>(&account)?;
+        if tick_array.pool_id != pool_account_key {
+            return Err(anyhow!(
+                "tick array {} does not belong to pool {}",
+                key,
+                pool_account_key
+            )
+            .into());
+        }
+        tick_arrays.push(tick_array);
+    }
+    let tick_array_keys = order_tick_arrays_for_swap(
+        &config.raydium_v3_program,
+        pool_state,
+        &bitmap_extension,
+        tick_arrays,
+        zero_for_one,
+    )?;
+    builder_trace!(
+        "build_swap: pool {}, zero_for_one {}, tick_arrays {:?}, other_amount_threshold {}",
+        pool_account_key,
+        zero_for_one,
+        tick_array_keys,
+        other_amount_threshold
+    );
+    let mut has_token_2022_mint = false;
+    for (mint, account) in keys.iter().zip(accounts) {
+        let account = account.ok_or_else(|| anyhow!("mint {} not found", mint))?;
+        has_token_2022_mint |= mint_token_program(mint, &account)? == spl_token_2022::id();
+    }
+
+    let mut remaining_accounts = vec![AccountMeta::new_readonly(tickarray_bitmap_extension, false)];
+    if has_token_2022_mint {
+        remaining_accounts.extend(
+            tick_array_keys
+                .iter()
+                .map(|tick_array| AccountMeta::new(*tick_array, false)),
+        );
+        swap_v2_instr(
+            config,
+            pool_state.amm_config,
+            pool_account_key,
+            input_vault,
+            output_vault,
+            pool_state.observation_key,
+            user_input_token,
+            user_output_token,
+            input_vault_mint,
+            output_vault_mint,
+            remaining_accounts,
+            amount,
+            other_amount_threshold,
+            sqrt_price_limit_x64,
+            is_base_input,
+        )
+    } else {
+        // the legacy swap takes the first tick array as a named account
+        remaining_accounts.extend(
+            tick_array_keys[1..]
+                .iter()
+                .map(|tick_array| AccountMeta::new(*tick_array, false)),
+        );
+        swap_instr(
+            config,
+            pool_state.amm_config,
+            pool_account_key,
+            input_vault,
+            output_vault,
+            pool_state.observation_key,
+            user_input_token,
+            user_output_token,
+            tick_array_keys[0],
+            remaining_accounts,
+            amount,
+            other_amount_threshold,
+            sqrt_price_limit_x64,
+            is_base_input,
+        )
+    }
+}
+
+/// Same as `build_swap_instr` for an exact output swap receiving `amount_out`, as quoted by
+/// `compute_swap_quote_exact_out`. The input is bounded by `quoted_amount_in` raised by
+/// `config.slippage`.
+pub fn swap_exact_out_instr(
+    rpc_client: &RpcClient,
+    config: &ClientConfig,
+    pool_account_key: Pubkey,
+    pool_state: &PoolState,
+    user_input_token: Pubkey,
+    user_output_token: Pubkey,
+    zero_for_one: bool,
+    tick_array_keys: Vec<Pubkey>,
+    amount_out: u64,
+    quoted_amount_in: u64,
+    sqrt_price_limit_x64: Option<u128>,
+) -> Result<Vec<Instruction>, ClientError> {
+    build_swap_instr(
+        rpc_client,
+        config,
+        pool_account_key,
+        pool_state,
+        user_input_token,
+        user_output_token,
+        zero_for_one,
+        tick_array_keys,
+        amount_out,
+        quoted_amount_in,
+        sqrt_price_limit_x64,
+        false,
+    )
+}
+
+/// Same as `build_swap_instr`, but drops trailing tick arrays until the transaction signed by
+/// the payer fits in `MAX_TRANSACTION_SIZE`. The returned flag is true if tick arrays were
+/// dropped, in which case the swap may run out of liquidity and need to be split.
+pub fn build_swap_within_size_limit(
+    rpc_client: &RpcClient,
+    config: &ClientConfig,
+    pool_account_key: Pubkey,
+    pool_state: &PoolState,
+    user_input_token: Pubkey,
+    user_output_token: Pubkey,
+    zero_for_one: bool,
+    tick_array_keys: Vec<Pubkey>,
+    amount: u64,
+    quoted_other_amount: u64,
+    sqrt_price_limit_x64: Option<u128>,
+    is_base_input: bool,
+) -> Result<(Vec<Instruction>, bool), ClientError> {
+    let mut droppable_tick_arrays = tick_array_keys.len().saturating_sub(1);
+    let mut instructions = build_swap_instr(
+        rpc_client,
+        config,
+        pool_account_key,
+        pool_state,
+        user_input_token,
+        user_output_token,
+        zero_for_one,
+        tick_array_keys,
+        amount,
+        quoted_other_amount,
+        sqrt_price_limit_x64,
+        is_base_input,
+    )?;
+    let mut truncated = false;
+    // tick arrays are the trailing accounts of the swap instruction
+    while estimate_tx_size(&instructions, 1) > MAX_TRANSACTION_SIZE && droppable_tick_arrays > 0 {
+        instructions.last_mut().unwrap().accounts.pop();
+        droppable_tick_arrays -= 1;
+        truncated = true;
+    }
+    if estimate_tx_size(&instructions, 1) > MAX_TRANSACTION_SIZE {
+        return Err(anyhow!(
+            "swap exceeds the transaction size limit even with a single tick array"
+        )
+        .into());
+    }
+    Ok((instructions, truncated))
+}
+
+/// Same as `build_swap_instr`, but compiled into a v0 transaction signed by the payer. The
+/// accounts found in `lookup_table` are referenced through the table, which leaves room for
+/// more tick arrays than a legacy transaction can hold. Without a table the transaction holds
+/// all accounts inline. The configured compute budget instructions are prepended.
+pub fn build_versioned_swap(
+    rpc_client: &RpcClient,
+    config: &ClientConfig,
+    lookup_table: Option<Pubkey>,
+    pool_account_key: Pubkey,
+    pool_state: &PoolState,
+    user_input_token: Pubkey,
+    user_output_token: Pubkey,
+    zero_for_one: bool,
+    tick_array_keys: Vec<Pubkey>,
+    amount: u64,
+    quoted_other_amount: u64,
+    sqrt_price_limit_x64: Option<u128>,
+    is_base_input: bool,
+) -> Result<VersionedTransaction, ClientError> {
+    let mut instructions = build_swap_instr(
+        rpc_client,
+        config,
+        pool_account_key,
+        pool_state,
+        user_input_token,
+        user_output_token,
+        zero_for_one,
+        tick_array_keys,
+        amount,
+        quoted_other_amount,
+        sqrt_price_limit_x64,
+        is_base_input,
+    )?;
+    config.apply_compute_budget(&mut instructions);
+
+    let mut lookup_table_accounts = Vec::new();
+    if let Some(lookup_table) = lookup_table {
+        let account = rpc_client.get_account(&lookup_table)?;
+        let table = AddressLookupTable::deserialize(&account.data)
+            .map_err(|e| anyhow!("invalid lookup table {}: {}", lookup_table, e))?;
+        lookup_table_accounts.push(AddressLookupTableAccount {
+            key: lookup_table,
+            addresses: table.addresses.to_vec(),
+        });
+    }
+    let payer = read_keypair_file(&config.payer_path)?;
+    let recent_blockhash = rpc_client.get_latest_blockhash()?;
+    let message = v0::Message::try_compile(
+        &payer.pubkey(),
+        &instructions,
+        &lookup_table_accounts,
+        recent_blockhash,
+    )?;
+    let transaction = VersionedTransaction::try_new(VersionedMessage::V0(message), &[&payer])?;
+    let size = bincode::serialized_size(&transaction)? as usize;
+    if size > MAX_TRANSACTION_SIZE {
+        return Err(anyhow!(
+            "versioned swap is {} bytes, exceeding the transaction size limit {}",
+            size,
+            MAX_TRANSACTION_SIZE
+        )
+        .into());
+    }
+    Ok(transaction)
+}
+
+/// Same as `build_swap_instr` with an exact input, swapping between the payer's associated token
+/// accounts. The output account is created if missing. When a side of the pool is wrapped SOL,
+/// the payer's wSOL account is created if needed and funded with `amount` lamports before the
+/// swap, and closed afterwards so that the swapped or remaining SOL is returned to the payer as
+/// lamports. Closing the account also unwraps any wSOL the payer already held in it.
+pub fn build_swap_with_sol(
+    config: &ClientConfig,
+    rpc_client: &RpcClient,
+    pool_account_key: Pubkey,
+    pool_state: &PoolState,
+    tick_array_keys: Vec<Pubkey>,
+    amount: u64,
+    quoted_amount_out: u64,
+    zero_for_one: bool,
+) -> Result<Vec<Instruction>, ClientError> {
+    let owner = read_keypair_file(&config.payer_path)?.pubkey();
+    let native_mint = spl_token::native_mint::id();
+    let (input_mint, output_mint) = if zero_for_one {
+        (pool_state.token_mint_0, pool_state.token_mint_1)
+    } else {
+        (pool_state.token_mint_1, pool_state.token_mint_0)
+    };
+    let (token_program_0, token_program_1) = resolve_token_programs(rpc_client, pool_state)?;
+    let (input_token_program, output_token_program) = if zero_for_one {
+        (token_program_0, token_program_1)
+    } else {
+        (token_program_1, token_program_0)
+    };
+    let user_input_token =
+        get_associated_token_address_with_program_id(&owner, &input_mint, &input_token_program);
+    let user_output_token =
+        get_associated_token_address_with_program_id(&owner, &output_mint, &output_token_program);
+
+    let mut instructions = Vec::new();
+    let wsol_account = if input_mint == native_mint {
+        Some(user_input_token)
+    } else if output_mint == native_mint {
+        Some(user_output_token)
+    } else {
+        None
+    };
+    if input_mint == native_mint {
+        instructions.push(create_ata_idempotent_instr(
+            &owner,
+            &native_mint,
+            &spl_token::id(),
+        ));
+        instructions.push(system_instruction::transfer(
+            &owner,
+            &user_input_token,
+            amount,
+        ));
+        instructions.push(spl_token::instruction::sync_native(
+            &spl_token::id(),
+            &user_input_token,
+        )?);
+    }
+    instructions.push(create_ata_idempotent_instr(
+        &owner,
+        &output_mint,
+        &output_token_program,
+    ));
+    instructions.extend(build_swap_instr(
+        rpc_client,
+        config,
+        pool_account_key,
+        pool_state,
+        user_input_token,
+        user_output_token,
+        zero_for_one,
+        tick_array_keys,
+        amount,
+        quoted_amount_out,
+        None,
+        true,
+    )?);
+    if let Some(wsol_account) = wsol_account {
+        instructions.push(spl_token::instruction::close_account(
+            &spl_token::id(),
+            &wsol_account,
+            &owner,
+            &owner,
+            &[],
+        )?);
+    }
+    Ok(instructions)
+}
+
+/// Build `initialize_reward` funded from `user_reward_token`. The operation state and the
+/// reward vault PDAs are derived, and the reward token program is picked from the owner of
+/// `reward_token_mint`, so both SPL Token and Token-2022 reward mints are supported.
 pub fn initialize_reward_instr(
+    rpc_client: &RpcClient,
     config: &ClientConfig,
     pool_account_key: Pubkey,
     amm_config: Pubkey,
-    operation_account_key: Pubkey,
     reward_token_mint: Pubkey,
-    reward_token_vault: Pubkey,
     user_reward_token: Pubkey,
-    reward_token_program: Pubkey,
     open_time: u64,
     end_time: u64,
     emissions_per_second_x64: u128,
-) -> Result<Vec<Instruction>> {
+) -> Result<Vec<Instruction>, ClientError> {
+    if open_time >= end_time {
+        return Err(anyhow!(
+            "reward open_time {} must be before end_time {}",
+            open_time,
+            end_time
+        )
+        .into());
+    }
+    let period = end_time - open_time;
+    if !(reward_period_limit::MIN_REWARD_PERIOD..=reward_period_limit::MAX_REWARD_PERIOD)
+        .contains(&period)
+    {
+        return Err(anyhow!(
+            "reward period {}s must be between {}s and {}s",
+            period,
+            reward_period_limit::MIN_REWARD_PERIOD,
+            reward_period_limit::MAX_REWARD_PERIOD
+        )
+        .into());
+    }
+    if emissions_per_second_x64 == 0 {
+        return Err(anyhow!("reward emissions_per_second_x64 must not be 0").into());
+    }
+    let reward_token_program = rpc_client.get_account(&reward_token_mint)?.owner;
+    if reward_token_program != spl_token::id() && reward_token_program != spl_token_2022::id() {
+        return Err(anyhow!(
+            "reward mint {} is not owned by a token program",
+            reward_token_mint
+        )
+        .into());
+    }
+    let (operation_account_key, __bump) =
+        Pubkey::find_program_address(&[OPERATION_SEED.as_bytes()], &config.raydium_v3_program);
+    let (reward_token_vault, __bump) = Pubkey::find_program_address(
+        &[
+            POOL_REWARD_VAULT_SEED.as_bytes(),
+            pool_account_key.to_bytes().as_ref(),
+            reward_token_mint.to_bytes().as_ref(),
+        ],
+        &config.raydium_v3_program,
+    );
+    builder_trace!(
+        "initialize_reward: pool {}, reward_mint {}, reward_vault {}, open_time {}, end_time {}, emissions_per_second_x64 {}",
+        pool_account_key,
+        reward_token_mint,
+        reward_token_vault,
+        open_time,
+        end_time,
+        emissions_per_second_x64
+    );
     let admin = read_keypair_file(&config.admin_path)?;
     let url = Cluster::Custom(config.http_url.clone(), config.ws_url.clone());
     // Client.
@@ -740,18 +1505,86 @@ pub fn initialize_reward_instr(
     Ok(instructions)
 }
 
+/// Check a new reward schedule against the current one before building `set_reward_params`,
+/// so that obviously invalid updates fail without an RPC round-trip to the program.
+fn check_reward_params(
+    reward_info: &RewardInfo,
+    reward_index: u8,
+    current_timestamp: u64,
+    open_time: u64,
+    end_time: u64,
+    emissions_per_second_x64: u128,
+) -> Result<()> {
+    if !reward_info.initialized() {
+        return Err(anyhow!("reward {} is not initialized", reward_index));
+    }
+    if open_time >= end_time {
+        return Err(anyhow!(
+            "reward open_time {} must be before end_time {}",
+            open_time,
+            end_time
+        ));
+    }
+    if open_time <= current_timestamp {
+        return Err(anyhow!(
+            "reward open_time {} must be in the future, now is {}",
+            open_time,
+            current_timestamp
+        ));
+    }
+    if emissions_per_second_x64 == 0 {
+        return Err(anyhow!("reward emissions_per_second_x64 must not be 0"));
+    }
+    let funded_end_time = reward_info.end_time;
+    let emitting =
+        reward_info.open_time <= current_timestamp && current_timestamp < funded_end_time;
+    if emitting && end_time < funded_end_time {
+        // the vault is already funded until the current end time
+        return Err(anyhow!(
+            "reward {} is funded until {}, end_time {} would cut the schedule short",
+            reward_index,
+            funded_end_time,
+            end_time
+        ));
+    }
+    Ok(())
+}
+
+/// Build `set_reward_params` for the reward `reward_index` of the pool. Any additional reward
+/// amount required by the new schedule is transferred from `user_reward_token`.
 pub fn set_reward_params_instr(
+    rpc_client: &RpcClient,
     config: &ClientConfig,
-    amm_config: Pubkey,
     pool_account_key: Pubkey,
-    reward_token_vault: Pubkey,
     user_reward_token: Pubkey,
-    operation_account_key: Pubkey,
     reward_index: u8,
     open_time: u64,
     end_time: u64,
     emissions_per_second_x64: u128,
-) -> Result<Vec<Instruction>> {
+) -> Result<Vec<Instruction>, ClientError> {
+    if reward_index as usize >= REWARD_NUM {
+        return Err(anyhow!(
+            "reward_index {} must be less than {}",
+            reward_index,
+            REWARD_NUM
+        )
+        .into());
+    }
+    let pool_state =
+        deserialize_anchor_account::<PoolState>(&rpc_client.get_account(&pool_account_key)?)?;
+    let clock: Clock = bincode::deserialize(&rpc_client.get_account(&sysvar::clock::id())?.data)?;
+    let reward_info = pool_state.reward_infos[reward_index as usize];
+    check_reward_params(
+        &reward_info,
+        reward_index,
+        clock.unix_timestamp as u64,
+        open_time,
+        end_time,
+        emissions_per_second_x64,
+    )?;
+    let (operation_account_key, __bump) =
+        Pubkey::find_program_address(&[OPERATION_SEED.as_bytes()], &config.raydium_v3_program);
+
     let admin = read_keypair_file(&config.admin_path)?;
     let url = Cluster::Custom(config.http_url.clone(), config.ws_url.clone());
     // Client.
@@ -759,16 +1592,16 @@ pub fn set_reward_params_instr(
     let program = client.program(config.raydium_v3_program)?;
 
     let remaining_accounts = vec![
-        AccountMeta::new(reward_token_vault, false),
+        AccountMeta::new(reward_info.token_vault, false),
         AccountMeta::new(user_reward_token, false),
-        AccountMeta::new_readonly(spl_token::id(), false),
+        AccountMeta::new_readonly(reward_info.token_mint, false),
     ];
 
     let instructions = program
         .request()
         .accounts(raydium_accounts::SetRewardParams {
             authority: program.payer(),
-            amm_config,
+            amm_config: pool_state.amm_config,
             pool_state: pool_account_key,
             operation_state: operation_account_key,
             token_program: spl_token::id(),
@@ -785,30 +1618,990 @@ pub fn set_reward_params_instr(
     Ok(instructions)
 }
 
-pub fn transfer_reward_owner(
+/// Build `transfer_reward_owner`, which sets `new_owner` as the owner of the pool and the
+/// authority of all its rewards. `authority` signs instead of the admin keypair when set, e.g.
+/// to encode the instruction for a multisig. The program only accepts the admin as signer, so
+/// any other authority is rejected before building.
+pub fn transfer_reward_owner_instr(
+    rpc_client: &RpcClient,
     config: &ClientConfig,
     pool_account_key: Pubkey,
     new_owner: Pubkey,
-    encode: bool,
     authority: Option<Pubkey>,
-) -> Result<Vec<Instruction>> {
+) -> Result<Vec<Instruction>, ClientError> {
+    let pool_state =
+        deserialize_anchor_account::<PoolState>(&rpc_client.get_account(&pool_account_key)?)?;
+
     let admin = read_keypair_file(&config.admin_path)?;
     let url = Cluster::Custom(config.http_url.clone(), config.ws_url.clone());
     // Client.
     let client = Client::new(url, Rc::new(admin));
     let program = client.program(config.raydium_v3_program)?;
+    let authority = authority.unwrap_or_else(|| program.payer());
+    if authority != raydium_amm_v3::admin::ID {
+        let current_owner = pool_state.owner;
+        return Err(anyhow!(
+            "{} can't transfer the reward owner of pool {} (currently {}), only the admin {} can",
+            authority,
+            pool_account_key,
+            current_owner,
+            raydium_amm_v3::admin::ID
+        )
+        .into());
+    }
 
     let instructions = program
         .request()
         .accounts(raydium_accounts::TransferRewardOwner {
-            authority: if encode {
-                authority.unwrap()
-            } else {
-                program.payer()
-            },
+            authority,
             pool_state: pool_account_key,
         })
         .args(raydium_instruction::TransferRewardOwner { new_owner })
         .instructions()?;
     Ok(instructions)
 }
+
+/// Build `update_pool_status`, which sets the status bitfield of the pool, see
+/// `PoolStatusFlags::to_status` to build `status` from named flags. Returns the operations the
+/// new status enables along with the instructions. `authority` signs instead of the admin
+/// keypair when set, any authority other than the admin is rejected before building.
+pub fn update_pool_status_instr(
+    config: &ClientConfig,
+    pool_account_key: Pubkey,
+    status: u8,
+    authority: Option<Pubkey>,
+) -> Result<(Vec<Instruction>, PoolStatusFlags), ClientError> {
+    let admin = read_keypair_file(&config.admin_path)?;
+    let url = Cluster::Custom(config.http_url.clone(), config.ws_url.clone());
+    // Client.
+    let client = Client::new(url, Rc::new(admin));
+    let program = client.program(config.raydium_v3_program)?;
+    let authority = authority.unwrap_or_else(|| program.payer());
+    if authority != raydium_amm_v3::admin::ID {
+        return Err(anyhow!(
+            "{} can't update the status of pool {}, only the admin {} can",
+            authority,
+            pool_account_key,
+            raydium_amm_v3::admin::ID
+        )
+        .into());
+    }
+    let flags = PoolStatusFlags::from_status(status);
+    builder_trace!(
+        "update_pool_status: pool {}, status {:#010b} ({})",
+        pool_account_key,
+        status,
+        flags
+    );
+
+    let instructions = program
+        .request()
+        .accounts(raydium_accounts::UpdatePoolStatus {
+            authority,
+            pool_state: pool_account_key,
+        })
+        .args(raydium_instruction::UpdatePoolStatus { status })
+        .instructions()?;
+    Ok((instructions, flags))
+}
+
+/// Build one zero liquidity `decrease_liquidity_v2` instruction per position, which collects the
+/// fees and rewards owed to the position. Positions without any fees or rewards owed, as of the
+/// `Clock` sysvar timestamp, are skipped. The recipient token accounts are the owner's associated
+/// token accounts, which must already exist. Use `pack_into_transactions` to split the result into
+/// transactions.
+pub fn build_collect_all(
+    rpc_client: &RpcClient,
+    owner: &Pubkey,
+    positions: &[PositionNftTokenInfo],
+    program_id: &Pubkey,
+) -> Result<Vec<Instruction>, ClientError> {
+    let position_keys = positions.iter().map(|p| p.position).collect::<Vec<_>>();
+    let personal_positions =
+        fetch_anchor_accounts::<PersonalPositionState>(rpc_client, &position_keys)?;
+
+    let pool_keys = personal_positions
+        .values()
+        .map(|p| p.pool_id)
+        .collect::<Vec<_>>();
+    let pools = fetch_anchor_accounts::<PoolState>(rpc_client, &pool_keys)?;
+
+    let mut mint_keys = Vec::new();
+    let mut tick_array_keys = Vec::new();
+    for personal_position in personal_positions.values() {
+        let pool_state = &pools[&personal_position.pool_id];
+        mint_keys.push(pool_state.token_mint_0);
+        mint_keys.push(pool_state.token_mint_1);
+        let reward_infos = pool_state.reward_infos;
+        for reward_info in reward_infos.iter().filter(|r| r.initialized()) {
+            mint_keys.push(reward_info.token_mint);
+        }
+        for tick_index in [
+            personal_position.tick_lower_index,
+            personal_position.tick_upper_index,
+        ] {
+            let start_index =
+                TickArrayState::get_array_start_index(tick_index, pool_state.tick_spacing);
+            tick_array_keys.push(get_tick_array_key(
+                program_id,
+                &personal_position.pool_id,
+                start_index,
+            ));
+        }
+    }
+    let tick_arrays = fetch_anchor_accounts::<TickArrayState>(rpc_client, &tick_array_keys)?;
+    mint_keys.sort();
+    mint_keys.dedup();
+    let mut mint_token_programs = HashMap::new();
+    for (mint, account) in mint_keys
+        .iter()
+        .zip(get_multiple_accounts_chunked(rpc_client, &mint_keys)?)
+    {
+        let account = account.ok_or_else(|| anyhow!("mint {} not found", mint))?;
+        mint_token_programs.insert(*mint, mint_token_program(mint, &account)?);
+    }
+
+    let clock: Clock = bincode::deserialize(&rpc_client.get_account(&sysvar::clock::id())?.data)?;
+    let mut instructions = Vec::new();
+    for position in positions {
+        let personal_position = &personal_positions[&position.position];
+        let pool_id = personal_position.pool_id;
+        let pool_state = &pools[&pool_id];
+        let tick_spacing = pool_state.tick_spacing;
+        let tick_array_lower_start_index =
+            TickArrayState::get_array_start_index(personal_position.tick_lower_index, tick_spacing);
+        let tick_array_upper_start_index =
+            TickArrayState::get_array_start_index(personal_position.tick_upper_index, tick_spacing);
+        let tick_array_lower =
+            get_tick_array_key(program_id, &pool_id, tick_array_lower_start_index);
+        let tick_array_upper =
+            get_tick_array_key(program_id, &pool_id, tick_array_upper_start_index);
+
+        let tick_lower = *tick_arrays[&tick_array_lower]
+            .clone()
+            .get_tick_state_mut(personal_position.tick_lower_index, tick_spacing)
+            .map_err(|e| anyhow!("{}", e))?;
+        let tick_upper = *tick_arrays[&tick_array_upper]
+            .clone()
+            .get_tick_state_mut(personal_position.tick_upper_index, tick_spacing)
+            .map_err(|e| anyhow!("{}", e))?;
+        let (fees_owed_0, fees_owed_1) =
+            compute_fees_owed(pool_state, personal_position, &tick_lower, &tick_upper);
+        let rewards_owed = compute_rewards_owed(
+            pool_state,
+            personal_position,
+            &tick_lower,
+            &tick_upper,
+            clock.unix_timestamp as u64,
+        )?;
+        if fees_owed_0 == 0 && fees_owed_1 == 0 && rewards_owed.iter().all(|r| *r == 0) {
+            continue;
+        }
+
+        let accounts = decrease_liquidity_v2_accounts(
+            owner,
+            position,
+            personal_position,
+            pool_state,
+            &mint_token_programs,
+            program_id,
+        );
+        instructions.push(Instruction {
+            program_id: *program_id,
+            accounts,
+            data: raydium_instruction::DecreaseLiquidityV2 {
+                liquidity: 0,
+                amount_0_min: 0,
+                amount_1_min: 0,
+            }
+            .data(),
+        });
+    }
+    Ok(instructions)
+}
+
+/// Build the instruction withdrawing `pct` of the position's liquidity, `pct` must be in
+/// (0.0, 1.0]. The minimum output amounts are the amounts received after transfer fees with
+/// `slippage` applied, fees and rewards owed are collected as well.
+pub fn decrease_liquidity_by_pct_instr(
+    rpc_client: &RpcClient,
+    owner: &Pubkey,
+    nft: &PositionNftTokenInfo,
+    position: &PersonalPositionState,
+    pct: f64,
+    pool: &PoolState,
+    slippage: f64,
+    program_id: &Pubkey,
+) -> Result<Vec<Instruction>, ClientError> {
+    if !(pct > 0.0 && pct <= 1.0) {
+        return Err(anyhow!("pct must be within (0.0, 1.0], got {}", pct).into());
+    }
+    PoolStatusFlags::from_pool(pool).ensure_withdraw_enabled()?;
+    validate_tick_range(
+        position.tick_lower_index,
+        position.tick_upper_index,
+        pool.tick_spacing,
+    )?;
+    let liquidity = if pct == 1.0 {
+        position.liquidity
+    } else {
+        (position.liquidity as f64 * pct).floor() as u128
+    };
+    if liquidity == 0 {
+        return Err(anyhow!(
+            "{} of position {} liquidity {} is zero",
+            pct,
+            nft.position,
+            position.liquidity
+        )
+        .into());
+    }
+    let (amount_0, amount_1) = liquidity_math::get_delta_amounts_signed(
+        pool.tick_current,
+        pool.sqrt_price_x64,
+        position.tick_lower_index,
+        position.tick_upper_index,
+        -i128::try_from(liquidity)?,
+    )
+    .map_err(|e| anyhow!("{}", e))?;
+
+    let mut mint_keys = vec![pool.token_mint_0, pool.token_mint_1];
+    let reward_infos = pool.reward_infos;
+    for reward_info in reward_infos.iter().filter(|r| r.initialized()) {
+        mint_keys.push(reward_info.token_mint);
+    }
+    let mint_accounts = get_multiple_accounts_chunked(rpc_client, &mint_keys)?;
+    let mut mint_token_programs = HashMap::new();
+    for (mint, account) in mint_keys.iter().zip(mint_accounts.iter()) {
+        let account = account
+            .as_ref()
+            .ok_or_else(|| anyhow!("mint {} not found", mint))?;
+        mint_token_programs.insert(*mint, mint_token_program(mint, account)?);
+    }
+    let epoch = rpc_client.get_epoch_info()?.epoch;
+    let mint_0 = StateWithExtensions::<Mint>::unpack(&mint_accounts[0].as_ref().unwrap().data)?;
+    let mint_1 = StateWithExtensions::<Mint>::unpack(&mint_accounts[1].as_ref().unwrap().data)?;
+    let amount_0_min = amount_with_slippage(
+        apply_transfer_fee(&mint_0, amount_0, epoch),
+        slippage,
+        false,
+    );
+    let amount_1_min = amount_with_slippage(
+        apply_transfer_fee(&mint_1, amount_1, epoch),
+        slippage,
+        false,
+    );
+
+    builder_trace!(
+        "decrease_liquidity_by_pct: position {}, pct {}, liquidity {}, amount_0_min {}, amount_1_min {}",
+        nft.position,
+        pct,
+        liquidity,
+        amount_0_min,
+        amount_1_min
+    );
+
+    Ok(vec![Instruction {
+        program_id: *program_id,
+        accounts: decrease_liquidity_v2_accounts(
+            owner,
+            nft,
+            position,
+            pool,
+            &mint_token_programs,
+            program_id,
+        ),
+        data: raydium_instruction::DecreaseLiquidityV2 {
+            liquidity,
+            amount_0_min,
+            amount_1_min,
+        }
+        .data(),
+    }])
+}
+
+/// The owner's side of a deposit into a pool: the pool mints with their token programs, the
+/// owner's associated token accounts of the mints, and the current epoch for the Token-2022
+/// transfer fees.
+struct DepositAccounts {
+    mint_keys: [Pubkey; 2],
+    mint_data: Vec<Vec<u8>>,
+    token_programs: Vec<Pubkey>,
+    token_accounts: [Pubkey; 2],
+    epoch: u64,
+}
+
+impl DepositAccounts {
+    /// Fetch the pool mints, and check that the owner's token accounts are usable when they
+    /// already exist.
+    fn load(
+        rpc_client: &RpcClient,
+        owner: &Pubkey,
+        pool_state: &PoolState,
+    ) -> Result<Self, ClientError> {
+        let mint_keys = [pool_state.token_mint_0, pool_state.token_mint_1];
+        let mut mint_data = Vec::with_capacity(mint_keys.len());
+        let mut token_programs = Vec::with_capacity(mint_keys.len());
+        for (mint, account) in mint_keys
+            .iter()
+            .zip(get_multiple_accounts_chunked(rpc_client, &mint_keys)?)
+        {
+            let account = account.ok_or_else(|| anyhow!("mint {} not found", mint))?;
+            token_programs.push(mint_token_program(mint, &account)?);
+            mint_data.push(account.data);
+        }
+        let token_accounts = [
+            get_associated_token_address_with_program_id(owner, &mint_keys[0], &token_programs[0]),
+            get_associated_token_address_with_program_id(owner, &mint_keys[1], &token_programs[1]),
+        ];
+        for (key, account) in token_accounts
+            .iter()
+            .zip(get_multiple_accounts_chunked(rpc_client, &token_accounts)?)
+        {
+            if let Some(account) = account {
+                assert_usable_token_account_data(key, &account.data)?;
+            }
+        }
+        Ok(DepositAccounts {
+            mint_keys,
+            mint_data,
+            token_programs,
+            token_accounts,
+            epoch: rpc_client.get_epoch_info()?.epoch,
+        })
+    }
+
+    fn mint(&self, index: usize) -> Result<StateWithExtensions<'_, Mint>, ClientError> {
+        Ok(StateWithExtensions::<Mint>::unpack(&self.mint_data[index])?)
+    }
+
+    /// The part of `amount` of the mint `index` the pool receives after the transfer fee.
+    fn amount_received(&self, index: usize, amount: u64) -> Result<u64, ClientError> {
+        Ok(apply_transfer_fee(&self.mint(index)?, amount, self.epoch))
+    }
+
+    /// The maximum amounts of a deposit of `liquidity` at the current price of the pool,
+    /// including the transfer fees and raised by `slippage`.
+    fn max_amounts(
+        &self,
+        pool_state: &PoolState,
+        tick_lower_index: i32,
+        tick_upper_index: i32,
+        liquidity: u128,
+        slippage: f64,
+    ) -> Result<(u64, u64), ClientError> {
+        let (amount_0, amount_1) = liquidity_math::get_delta_amounts_signed(
+            pool_state.tick_current,
+            pool_state.sqrt_price_x64,
+            tick_lower_index,
+            tick_upper_index,
+            i128::try_from(liquidity)?,
+        )
+        .map_err(|e| anyhow!("{}", e))?;
+        let mut max_amounts = [amount_0, amount_1];
+        for (index, amount) in max_amounts.iter_mut().enumerate() {
+            let transfer_fee = get_transfer_inverse_fee(&self.mint(index)?, self.epoch, *amount);
+            *amount = amount_with_slippage(amount.saturating_add(transfer_fee), slippage, true);
+        }
+        Ok((max_amounts[0], max_amounts[1]))
+    }
+
+    /// Create the owner's token accounts of the mints if missing.
+    fn create_token_accounts_instrs(&self, owner: &Pubkey) -> Vec<Instruction> {
+        self.mint_keys
+            .iter()
+            .zip(self.token_programs.iter())
+            .map(|(mint, token_program)| create_ata_idempotent_instr(owner, mint, token_program))
+            .collect()
+    }
+}
+
+/// The tick array bitmap extension of the pool, which the liquidity instructions take as a
+/// remaining account when the position's tick arrays overflow the default bitmap.
+fn tickarray_bitmap_extension_meta(
+    pool_state: &PoolState,
+    pool_id: &Pubkey,
+    tick_array_lower_start_index: i32,
+    tick_array_upper_start_index: i32,
+    program_id: &Pubkey,
+) -> Option<AccountMeta> {
+    if !pool_state.is_overflow_default_tickarray_bitmap(vec![
+        tick_array_lower_start_index,
+        tick_array_upper_start_index,
+    ]) {
+        return None;
+    }
+    let (tickarray_bitmap_extension, __bump) = Pubkey::find_program_address(
+        &[POOL_TICK_ARRAY_BITMAP_SEED.as_bytes(), pool_id.as_ref()],
+        program_id,
+    );
+    Some(AccountMeta::new(tickarray_bitmap_extension, false))
+}
+
+/// Build the instructions adding the liquidity that `amount_0_desired` and `amount_1_desired`
+/// provide at the current price to the position. The owner's associated token accounts of
+/// the pool mints are created if missing, with the token program of each mint. The maximum
+/// amounts cover the Token-2022 transfer fees and are raised by `slippage`.
+pub fn build_increase_liquidity(
+    rpc_client: &RpcClient,
+    owner: &Pubkey,
+    nft: &PositionNftTokenInfo,
+    amount_0_desired: u64,
+    amount_1_desired: u64,
+    slippage: f64,
+    program_id: &Pubkey,
+) -> Result<Vec<Instruction>, ClientError> {
+    let personal_position = deserialize_anchor_account::<PersonalPositionState>(
+        &rpc_client.get_account(&nft.position)?,
+    )?;
+    let pool_id = personal_position.pool_id;
+    let pool_state = deserialize_anchor_account::<PoolState>(&rpc_client.get_account(&pool_id)?)?;
+    PoolStatusFlags::from_pool(&pool_state).ensure_deposit_enabled()?;
+    validate_tick_range(
+        personal_position.tick_lower_index,
+        personal_position.tick_upper_index,
+        pool_state.tick_spacing,
+    )?;
+    let deposit = DepositAccounts::load(rpc_client, owner, &pool_state)?;
+
+    let tick_lower_index = personal_position.tick_lower_index;
+    let tick_upper_index = personal_position.tick_upper_index;
+    // the pool receives the desired amounts minus the transfer fees
+    let liquidity = amounts_to_liquidity(
+        pool_state.sqrt_price_x64,
+        tick_lower_index,
+        tick_upper_index,
+        deposit.amount_received(0, amount_0_desired)?,
+        deposit.amount_received(1, amount_1_desired)?,
+    )?;
+    if liquidity == 0 {
+        return Err(anyhow!(
+            "amounts {} and {} add no liquidity to position {}",
+            amount_0_desired,
+            amount_1_desired,
+            nft.position
+        )
+        .into());
+    }
+    let (amount_0_max, amount_1_max) = deposit.max_amounts(
+        &pool_state,
+        tick_lower_index,
+        tick_upper_index,
+        liquidity,
+        slippage,
+    )?;
+
+    let tick_spacing = pool_state.tick_spacing;
+    let tick_array_lower_start_index =
+        TickArrayState::get_array_start_index(tick_lower_index, tick_spacing);
+    let tick_array_upper_start_index =
+        TickArrayState::get_array_start_index(tick_upper_index, tick_spacing);
+    let (protocol_position, __bump) = Pubkey::find_program_address(
+        &[
+            POSITION_SEED.as_bytes(),
+            pool_id.to_bytes().as_ref(),
+            &tick_lower_index.to_be_bytes(),
+            &tick_upper_index.to_be_bytes(),
+        ],
+        program_id,
+    );
+    let mut accounts = raydium_accounts::IncreaseLiquidityV2 {
+        nft_owner: *owner,
+        nft_account: nft.key,
+        pool_state: pool_id,
+        protocol_position,
+        personal_position: nft.position,
+        tick_array_lower: get_tick_array_key(program_id, &pool_id, tick_array_lower_start_index),
+        tick_array_upper: get_tick_array_key(program_id, &pool_id, tick_array_upper_start_index),
+        token_account_0: deposit.token_accounts[0],
+        token_account_1: deposit.token_accounts[1],
+        token_vault_0: pool_state.token_vault_0,
+        token_vault_1: pool_state.token_vault_1,
+        token_program: spl_token::id(),
+        token_program_2022: spl_token_2022::id(),
+        vault_0_mint: deposit.mint_keys[0],
+        vault_1_mint: deposit.mint_keys[1],
+    }
+    .to_account_metas(None);
+    accounts.extend(tickarray_bitmap_extension_meta(
+        &pool_state,
+        &pool_id,
+        tick_array_lower_start_index,
+        tick_array_upper_start_index,
+        program_id,
+    ));
+    builder_trace!(
+        "increase_liquidity: position {}, liquidity {}, amount_0_max {}, amount_1_max {}",
+        nft.position,
+        liquidity,
+        amount_0_max,
+        amount_1_max
+    );
+
+    let mut instructions = deposit.create_token_accounts_instrs(owner);
+    instructions.push(Instruction {
+        program_id: *program_id,
+        accounts,
+        data: raydium_instruction::IncreaseLiquidityV2 {
+            liquidity,
+            amount_0_max,
+            amount_1_max,
+            base_flag: None,
+        }
+        .data(),
+    });
+    Ok(instructions)
+}
+
+/// Build the instructions opening a position of `liquidity` over `[tick_lower_index,
+/// tick_upper_index)` in `pool_id`, paid by and minted to `owner`, without nft metadata. The
+/// position nft mint keypair is generated and returned, the transaction must be signed with it
+/// along with `owner`. The owner's associated token accounts of the pool mints are created if
+/// missing, and the maximum amounts cover the Token-2022 transfer fees raised by `slippage`.
+/// Fails with `ClientError::InsufficientSol` when `owner` can't pay the transaction fee and the
+/// rent of the position accounts and of the tick arrays the position initializes.
+pub fn build_open_position(
+    rpc_client: &RpcClient,
+    owner: &Pubkey,
+    pool_id: &Pubkey,
+    tick_lower_index: i32,
+    tick_upper_index: i32,
+    liquidity: u128,
+    slippage: f64,
+    program_id: &Pubkey,
+) -> Result<(Vec<Instruction>, Keypair), ClientError> {
+    let pool_state = deserialize_anchor_account::<PoolState>(&rpc_client.get_account(pool_id)?)?;
+    PoolStatusFlags::from_pool(&pool_state).ensure_deposit_enabled()?;
+    validate_tick_range(tick_lower_index, tick_upper_index, pool_state.tick_spacing)?;
+    if liquidity == 0 {
+        return Err(anyhow!("liquidity must be positive").into());
+    }
+    let deposit = DepositAccounts::load(rpc_client, owner, &pool_state)?;
+    let (amount_0_max, amount_1_max) = deposit.max_amounts(
+        &pool_state,
+        tick_lower_index,
+        tick_upper_index,
+        liquidity,
+        slippage,
+    )?;
+
+    let nft_mint = Keypair::new();
+    let nft_mint_key = nft_mint.pubkey();
+    let tick_spacing = pool_state.tick_spacing;
+    let tick_array_lower_start_index =
+        TickArrayState::get_array_start_index(tick_lower_index, tick_spacing);
+    let tick_array_upper_start_index =
+        TickArrayState::get_array_start_index(tick_upper_index, tick_spacing);
+    let tick_array_lower = get_tick_array_key(program_id, pool_id, tick_array_lower_start_index);
+    let tick_array_upper = get_tick_array_key(program_id, pool_id, tick_array_upper_start_index);
+    let (metadata_account, _bump) = Pubkey::find_program_address(
+        &[
+            Metadata::PREFIX,
+            mpl_token_metadata::ID.to_bytes().as_ref(),
+            nft_mint_key.to_bytes().as_ref(),
+        ],
+        &mpl_token_metadata::ID,
+    );
+    let (protocol_position, __bump) = Pubkey::find_program_address(
+        &[
+            POSITION_SEED.as_bytes(),
+            pool_id.to_bytes().as_ref(),
+            &tick_lower_index.to_be_bytes(),
+            &tick_upper_index.to_be_bytes(),
+        ],
+        program_id,
+    );
+    let (personal_position, __bump) = Pubkey::find_program_address(
+        &[POSITION_SEED.as_bytes(), nft_mint_key.to_bytes().as_ref()],
+        program_id,
+    );
+    let mut accounts = raydium_accounts::OpenPositionV2 {
+        payer: *owner,
+        position_nft_owner: *owner,
+        position_nft_mint: nft_mint_key,
+        position_nft_account: spl_associated_token_account::get_associated_token_address(
+            owner,
+            &nft_mint_key,
+        ),
+        metadata_account,
+        pool_state: *pool_id,
+        protocol_position,
+        tick_array_lower,
+        tick_array_upper,
+        personal_position,
+        token_account_0: deposit.token_accounts[0],
+        token_account_1: deposit.token_accounts[1],
+        token_vault_0: pool_state.token_vault_0,
+        token_vault_1: pool_state.token_vault_1,
+        rent: sysvar::rent::id(),
+        system_program: system_program::id(),
+        token_program: spl_token::id(),
+        associated_token_program: spl_associated_token_account::id(),
+        metadata_program: mpl_token_metadata::ID,
+        token_program_2022: spl_token_2022::id(),
+        vault_0_mint: deposit.mint_keys[0],
+        vault_1_mint: deposit.mint_keys[1],
+    }
+    .to_account_metas(None);
+    accounts.extend(tickarray_bitmap_extension_meta(
+        &pool_state,
+        pool_id,
+        tick_array_lower_start_index,
+        tick_array_upper_start_index,
+        program_id,
+    ));
+    builder_trace!(
+        "open_position: pool {}, personal_position {}, ticks [{}, {}], liquidity {}, amount_0_max {}, amount_1_max {}",
+        pool_id,
+        personal_position,
+        tick_lower_index,
+        tick_upper_index,
+        liquidity,
+        amount_0_max,
+        amount_1_max
+    );
+
+    let mut instructions = deposit.create_token_accounts_instrs(owner);
+    instructions.push(Instruction {
+        program_id: *program_id,
+        accounts,
+        data: raydium_instruction::OpenPositionV2 {
+            liquidity,
+            amount_0_max,
+            amount_1_max,
+            tick_lower_index,
+            tick_upper_index,
+            tick_array_lower_start_index,
+            tick_array_upper_start_index,
+            with_metadata: false,
+            base_flag: None,
+        }
+        .data(),
+    });
+
+    // the tick arrays not initialized yet are created by the position, at the owner's expense
+    let mut tick_array_keys = vec![tick_array_lower];
+    if tick_array_upper != tick_array_lower {
+        tick_array_keys.push(tick_array_upper);
+    }
+    let missing_tick_arrays = get_multiple_accounts_chunked(rpc_client, &tick_array_keys)?
+        .iter()
+        .filter(|account| account.is_none())
+        .count();
+    let message = Message::new_with_blockhash(
+        &instructions,
+        Some(owner),
+        &rpc_client.get_latest_blockhash()?,
+    );
+    let lamports_needed = estimate_rent_for_open_position(rpc_client, &spl_token::id(), false)?
+        + estimate_rent_for_tick_arrays(rpc_client, missing_tick_arrays)?
+        + rpc_client.get_fee_for_message(&message)?;
+    ensure_sufficient_sol(rpc_client, owner, lamports_needed)?;
+    Ok((instructions, nft_mint))
+}
+
+/// Build a full exit of the position, grouped into transactions paid by `owner`: withdraw all
+/// liquidity while collecting the fees and rewards owed, then close the position. The
+/// withdrawal accepts any output amount, so rounding dust never blocks the close, which makes
+/// the exit unprotected against price moves between building and sending.
+pub fn build_full_exit(
+    rpc_client: &RpcClient,
+    owner: &Pubkey,
+    nft: &PositionNftTokenInfo,
+    program_id: &Pubkey,
+) -> Result<Vec<Vec<Instruction>>, ClientError> {
+    // the position nft is burnt by the close, which a frozen nft account prevents
+    assert_usable_token_account_data(&nft.key, &rpc_client.get_account(&nft.key)?.data)?;
+    let personal_position = deserialize_anchor_account::<PersonalPositionState>(
+        &rpc_client.get_account(&nft.position)?,
+    )?;
+    let pool_state = deserialize_anchor_account::<PoolState>(
+        &rpc_client.get_account(&personal_position.pool_id)?,
+    )?;
+    // the program skips burning the liquidity when decreasing is disabled, failing the close
+    if personal_position.liquidity > 0 {
+        PoolStatusFlags::from_pool(&pool_state).ensure_withdraw_enabled()?;
+    }
+    validate_tick_range(
+        personal_position.tick_lower_index,
+        personal_position.tick_upper_index,
+        pool_state.tick_spacing,
+    )?;
+    let mut mint_keys = vec![pool_state.token_mint_0, pool_state.token_mint_1];
+    let reward_infos = pool_state.reward_infos;
+    for reward_info in reward_infos.iter().filter(|r| r.initialized()) {
+        mint_keys.push(reward_info.token_mint);
+    }
+    let mut mint_token_programs = HashMap::new();
+    for (mint, account) in mint_keys
+        .iter()
+        .zip(get_multiple_accounts_chunked(rpc_client, &mint_keys)?)
+    {
+        let account = account.ok_or_else(|| anyhow!("mint {} not found", mint))?;
+        mint_token_programs.insert(*mint, mint_token_program(mint, &account)?);
+    }
+    builder_trace!(
+        "full_exit: position {}, liquidity {}",
+        nft.position,
+        personal_position.liquidity
+    );
+
+    // decreasing zero liquidity still collects the fees and rewards owed, which must be zero
+    // for the close to succeed
+    let mut instructions = vec![Instruction {
+        program_id: *program_id,
+        accounts: decrease_liquidity_v2_accounts(
+            owner,
+            nft,
+            &personal_position,
+            &pool_state,
+            &mint_token_programs,
+            program_id,
+        ),
+        data: raydium_instruction::DecreaseLiquidityV2 {
+            liquidity: personal_position.liquidity,
+            amount_0_min: 0,
+            amount_1_min: 0,
+        }
+        .data(),
+    }];
+    instructions.extend(close_position_instr(owner, nft, 0, program_id)?);
+    let instructions = instructions
+        .into_iter()
+        .map(|instruction| (instruction, DEFAULT_INSTRUCTION_COMPUTE_UNIT_LIMIT))
+        .collect();
+    Ok(pack_into_transactions(instructions, MAX_COMPUTE_UNIT_LIMIT))
+}
+
+/// Compute units the runtime allocates to an instruction when the transaction sets no limit.
+pub const DEFAULT_INSTRUCTION_COMPUTE_UNIT_LIMIT: u32 = 200_000;
+/// Compute units consumed by a compute budget program instruction.
+pub const COMPUTE_BUDGET_INSTRUCTION_UNITS: u32 = 150;
+
+/// Greedily split `instructions` into groups that each fit into a single transaction signed by
+/// the payer alone and whose compute units stay within `max_cu`. Each instruction is paired with
+/// its estimated compute units, e.g. the units consumed in a simulation,
+/// `COMPUTE_BUDGET_INSTRUCTION_UNITS` for compute budget instructions, or
+/// `DEFAULT_INSTRUCTION_COMPUTE_UNIT_LIMIT` when unknown. An instruction that alone exceeds a
+/// limit is still returned as its own group.
+pub fn pack_into_transactions(
+    instructions: Vec<(Instruction, u32)>,
+    max_cu: u32,
+) -> Vec<Vec<Instruction>> {
+    let mut groups = Vec::new();
+    let mut group: Vec<Instruction> = Vec::new();
+    let mut group_cu = 0u32;
+    for (instruction, instruction_cu) in instructions {
+        group.push(instruction);
+        group_cu = group_cu.saturating_add(instruction_cu);
+        let size = estimate_tx_size(&group, 1);
+        if (size > MAX_TRANSACTION_SIZE || group_cu > max_cu) && group.len() > 1 {
+            let instruction = group.pop().unwrap();
+            groups.push(std::mem::replace(&mut group, vec![instruction]));
+            group_cu = instruction_cu;
+        }
+    }
+    if !group.is_empty() {
+        groups.push(group);
+    }
+    groups
+}
+
+/// Accounts of `decrease_liquidity_v2` for the position, including the bitmap extension when
+/// the position's tick arrays overflow the default bitmap and the accounts of the pool's
+/// initialized rewards. `mint_token_programs` must hold the pool mints and reward mints.
+fn decrease_liquidity_v2_accounts(
+    owner: &Pubkey,
+    position: &PositionNftTokenInfo,
+    personal_position: &PersonalPositionState,
+    pool_state: &PoolState,
+    mint_token_programs: &HashMap<Pubkey, Pubkey>,
+    program_id: &Pubkey,
+) -> Vec<AccountMeta> {
+    let pool_id = personal_position.pool_id;
+    let tick_spacing = pool_state.tick_spacing;
+    let tick_array_lower_start_index =
+        TickArrayState::get_array_start_index(personal_position.tick_lower_index, tick_spacing);
+    let tick_array_upper_start_index =
+        TickArrayState::get_array_start_index(personal_position.tick_upper_index, tick_spacing);
+    let tick_array_lower = get_tick_array_key(program_id, &pool_id, tick_array_lower_start_index);
+    let tick_array_upper = get_tick_array_key(program_id, &pool_id, tick_array_upper_start_index);
+    let reward_infos = pool_state.reward_infos;
+    let (protocol_position, __bump) = Pubkey::find_program_address(
+        &[
+            POSITION_SEED.as_bytes(),
+            pool_id.to_bytes().as_ref(),
+            &personal_position.tick_lower_index.to_be_bytes(),
+            &personal_position.tick_upper_index.to_be_bytes(),
+        ],
+        program_id,
+    );
+    let mut accounts = raydium_accounts::DecreaseLiquidityV2 {
+        nft_owner: *owner,
+        nft_account: position.key,
+        personal_position: position.position,
+        pool_state: pool_id,
+        protocol_position,
+        token_vault_0: pool_state.token_vault_0,
+        token_vault_1: pool_state.token_vault_1,
+        tick_array_lower,
+        tick_array_upper,
+        recipient_token_account_0: get_associated_token_address_with_program_id(
+            owner,
+            &pool_state.token_mint_0,
+            &mint_token_programs[&pool_state.token_mint_0],
+        ),
+        recipient_token_account_1: get_associated_token_address_with_program_id(
+            owner,
+            &pool_state.token_mint_1,
+            &mint_token_programs[&pool_state.token_mint_1],
+        ),
+        token_program: spl_token::id(),
+        token_program_2022: spl_token_2022::id(),
+        memo_program: spl_memo::id(),
+        vault_0_mint: pool_state.token_mint_0,
+        vault_1_mint: pool_state.token_mint_1,
+    }
+    .to_account_metas(None);
+    accounts.extend(tickarray_bitmap_extension_meta(
+        pool_state,
+        &pool_id,
+        tick_array_lower_start_index,
+        tick_array_upper_start_index,
+        program_id,
+    ));
+    for reward_info in reward_infos.iter().filter(|r| r.initialized()) {
+        accounts.push(AccountMeta::new(reward_info.token_vault, false));
+        accounts.push(AccountMeta::new(
+            get_associated_token_address_with_program_id(
+                owner,
+                &reward_info.token_mint,
+                &mint_token_programs[&reward_info.token_mint],
+            ),
+            false,
+        ));
+        accounts.push(AccountMeta::new_readonly(reward_info.token_mint, false));
+    }
+    accounts
+}
+
+pub(crate) fn fetch_anchor_accounts<T: AccountDeserialize + Discriminator>(
+    rpc_client: &RpcClient,
+    keys: &[Pubkey],
+) -> Result<HashMap<Pubkey, T>> {
+    let mut keys = keys.to_vec();
+    keys.sort();
+    keys.dedup();
+    let mut accounts = HashMap::new();
+    for (key, account) in keys
+        .iter()
+        .zip(get_multiple_accounts_chunked(rpc_client, &keys)?)
+    {
+        let account = account.ok_or_else(|| anyhow!("account {} not found", key))?;
+        accounts.insert(*key, deserialize_anchor_account::<T>(&account)?);
+    }
+    Ok(accounts)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn build_nft(program: Pubkey) -> PositionNftTokenInfo {
+        PositionNftTokenInfo {
+            key: Pubkey::new_unique(),
+            program,
+            position: Pubkey::new_unique(),
+            mint: Pubkey::new_unique(),
+            amount: 1,
+            decimals: 0,
+        }
+    }
+
+    #[test]
+    fn close_position_token_2022_nft_test() {
+        let owner = Pubkey::new_unique();
+        let nft = build_nft(spl_token_2022::id());
+        let instructions = close_position_instr(&owner, &nft, 0, &raydium_amm_v3::id()).unwrap();
+        assert_eq!(instructions.len(), 1);
+        let accounts = &instructions[0].accounts;
+        assert_eq!(accounts.len(), 6);
+        assert_eq!(accounts[1].pubkey, nft.mint);
+        assert_eq!(accounts[2].pubkey, nft.key);
+        assert_eq!(accounts[3].pubkey, nft.position);
+        // the nft is burnt with the token program holding it
+        assert_eq!(accounts[5].pubkey, spl_token_2022::id());
+        assert!(!accounts[5].is_writable);
+    }
+
+    #[test]
+    fn close_position_non_token_program_nft_test() {
+        let owner = Pubkey::new_unique();
+        let nft = build_nft(system_program::id());
+        assert!(close_position_instr(&owner, &nft, 0, &raydium_amm_v3::id()).is_err());
+    }
+
+    /// An instruction signed by `payer` with `data_len` bytes of data.
+    fn build_instruction(payer: &Pubkey, data_len: usize) -> Instruction {
+        Instruction::new_with_bytes(
+            raydium_amm_v3::id(),
+            &vec![0; data_len],
+            vec![AccountMeta::new(*payer, true)],
+        )
+    }
+
+    #[test]
+    fn pack_into_transactions_size_limit_test() {
+        let payer = Pubkey::new_unique();
+        let instructions = (0..6)
+            .map(|i| (build_instruction(&payer, 400 + i), 1_000))
+            .collect::<Vec<_>>();
+        let groups = pack_into_transactions(instructions, MAX_COMPUTE_UNIT_LIMIT);
+        assert_eq!(
+            groups.iter().map(|group| group.len()).collect::<Vec<_>>(),
+            vec![2, 2, 2]
+        );
+        for group in &groups {
+            assert!(estimate_tx_size(group, 1) <= MAX_TRANSACTION_SIZE);
+        }
+        // the instructions keep their order
+        let data_lens = groups
+            .iter()
+            .flatten()
+            .map(|instruction| instruction.data.len())
+            .collect::<Vec<_>>();
+        assert_eq!(data_lens, (400..406).collect::<Vec<_>>());
+    }
+
+    #[test]
+    fn pack_into_transactions_cu_limit_test() {
+        let payer = Pubkey::new_unique();
+        let instructions = (0..5)
+            .map(|_| (build_instruction(&payer, 8), 500_000))
+            .collect::<Vec<_>>();
+        let groups = pack_into_transactions(instructions, MAX_COMPUTE_UNIT_LIMIT);
+        assert_eq!(
+            groups.iter().map(|group| group.len()).collect::<Vec<_>>(),
+            vec![2, 2, 1]
+        );
+    }
+
+    #[test]
+    fn pack_into_transactions_oversized_instruction_test() {
+        let payer = Pubkey::new_unique();
+        let instructions = vec![
+            (build_instruction(&payer, 8), 1_000),
+            (build_instruction(&payer, MAX_TRANSACTION_SIZE), 1_000),
+            (build_instruction(&payer, 8), 1_000),
+            (build_instruction(&payer, 8), MAX_COMPUTE_UNIT_LIMIT + 1),
+            (build_instruction(&payer, 8), 1_000),
+        ];
+        let groups = pack_into_transactions(instructions, MAX_COMPUTE_UNIT_LIMIT);
+        // each instruction over a limit is alone in its group
+        assert_eq!(
+            groups.iter().map(|group| group.len()).collect::<Vec<_>>(),
+            vec![1, 1, 1, 1, 1]
+        );
+        assert_eq!(groups[1][0].data.len(), MAX_TRANSACTION_SIZE);
+    }
+}
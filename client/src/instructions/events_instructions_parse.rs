@@ -1,16 +1,23 @@
-use anchor_client::ClientError;
+use anchor_client::ClientError as AnchorClientError;
 use anchor_lang::prelude::Pubkey;
 use anchor_lang::Discriminator;
 use anyhow::Result;
 use colorful::Color;
 use colorful::Colorful;
+use raydium_amm_v3::error::ErrorCode;
 use raydium_amm_v3::instruction;
 use raydium_amm_v3::instructions::*;
 use raydium_amm_v3::states::*;
 use regex::Regex;
+use solana_client::{rpc_client::RpcClient, rpc_config::RpcTransactionConfig};
+use solana_sdk::{commitment_config::CommitmentConfig, signature::Signature};
 use solana_transaction_status::{
-    option_serializer::OptionSerializer, EncodedTransaction, UiTransactionStatusMeta,
+    option_serializer::OptionSerializer, EncodedTransaction, UiTransactionEncoding,
+    UiTransactionStatusMeta,
 };
+use std::sync::OnceLock;
+
+use super::super::ClientError;
 
 const PROGRAM_LOG: &str = "Program log: ";
 const PROGRAM_DATA: &str = "Program data: ";
@@ -21,10 +28,146 @@ pub enum InstructionDecodeType {
     Base58,
 }
 
+/// An event emitted by the program
+#[derive(Debug)]
+pub enum RaydiumEvent {
+    ConfigChangeEvent(ConfigChangeEvent),
+    CollectPersonalFeeEvent(CollectPersonalFeeEvent),
+    CollectProtocolFeeEvent(CollectProtocolFeeEvent),
+    CollectFundFeeEvent(CollectFundFeeEvent),
+    CloseSupportMintAssociatedEvent(CloseSupportMintAssociatedEvent),
+    CreatePersonalPositionEvent(CreatePersonalPositionEvent),
+    CreateSupportMintAssociatedEvent(CreateSupportMintAssociatedEvent),
+    DecreaseLiquidityEvent(DecreaseLiquidityEvent),
+    IncreaseLiquidityEvent(IncreaseLiquidityEvent),
+    LiquidityCalculateEvent(LiquidityCalculateEvent),
+    LiquidityChangeEvent(LiquidityChangeEvent),
+    SwapEvent(SwapEvent),
+    PoolCreatedEvent(PoolCreatedEvent),
+    UpdateRewardInfosEvent(UpdateRewardInfosEvent),
+    UpdateSupportMintAssociatedEvent(UpdateSupportMintAssociatedEvent),
+}
+
+impl RaydiumEvent {
+    /// Decode an event from its Anchor event data, the 8 bytes discriminator followed by the
+    /// borsh serialized event. Return `None` for unknown discriminators or malformed data.
+    pub fn try_from_data(data: &[u8]) -> Option<Self> {
+        decode_raydium_event(data).ok().flatten()
+    }
+}
+
+/// Decode an event from its Anchor event data. Unknown discriminators return `Ok(None)`, known
+/// ones failing to deserialize return the error.
+fn decode_raydium_event(data: &[u8]) -> Result<Option<RaydiumEvent>, AnchorClientError> {
+    if data.len() < 8 {
+        return Ok(None);
+    }
+    let (disc, mut slice) = data.split_at(8);
+    let event = match disc {
+        ConfigChangeEvent::DISCRIMINATOR => {
+            RaydiumEvent::ConfigChangeEvent(decode_event(&mut slice)?)
+        }
+        CollectPersonalFeeEvent::DISCRIMINATOR => {
+            RaydiumEvent::CollectPersonalFeeEvent(decode_event(&mut slice)?)
+        }
+        CollectProtocolFeeEvent::DISCRIMINATOR => {
+            RaydiumEvent::CollectProtocolFeeEvent(decode_event(&mut slice)?)
+        }
+        CollectFundFeeEvent::DISCRIMINATOR => {
+            RaydiumEvent::CollectFundFeeEvent(decode_event(&mut slice)?)
+        }
+        CloseSupportMintAssociatedEvent::DISCRIMINATOR => {
+            RaydiumEvent::CloseSupportMintAssociatedEvent(decode_event(&mut slice)?)
+        }
+        CreatePersonalPositionEvent::DISCRIMINATOR => {
+            RaydiumEvent::CreatePersonalPositionEvent(decode_event(&mut slice)?)
+        }
+        CreateSupportMintAssociatedEvent::DISCRIMINATOR => {
+            RaydiumEvent::CreateSupportMintAssociatedEvent(decode_event(&mut slice)?)
+        }
+        DecreaseLiquidityEvent::DISCRIMINATOR => {
+            RaydiumEvent::DecreaseLiquidityEvent(decode_event(&mut slice)?)
+        }
+        IncreaseLiquidityEvent::DISCRIMINATOR => {
+            RaydiumEvent::IncreaseLiquidityEvent(decode_event(&mut slice)?)
+        }
+        LiquidityCalculateEvent::DISCRIMINATOR => {
+            RaydiumEvent::LiquidityCalculateEvent(decode_event(&mut slice)?)
+        }
+        LiquidityChangeEvent::DISCRIMINATOR => {
+            RaydiumEvent::LiquidityChangeEvent(decode_event(&mut slice)?)
+        }
+        SwapEvent::DISCRIMINATOR => RaydiumEvent::SwapEvent(decode_event(&mut slice)?),
+        PoolCreatedEvent::DISCRIMINATOR => {
+            RaydiumEvent::PoolCreatedEvent(decode_event(&mut slice)?)
+        }
+        UpdateRewardInfosEvent::DISCRIMINATOR => {
+            RaydiumEvent::UpdateRewardInfosEvent(decode_event(&mut slice)?)
+        }
+        UpdateSupportMintAssociatedEvent::DISCRIMINATOR => {
+            RaydiumEvent::UpdateSupportMintAssociatedEvent(decode_event(&mut slice)?)
+        }
+        _ => return Ok(None),
+    };
+    Ok(Some(event))
+}
+
+/// Fetch a confirmed transaction and decode the events emitted by `program_id`, including
+/// events emitted from CPI calls into the program.
+pub fn parse_events_from_signature(
+    rpc_client: &RpcClient,
+    signature: &Signature,
+    program_id: &Pubkey,
+) -> Result<Vec<RaydiumEvent>, ClientError> {
+    let transaction = rpc_client.get_transaction_with_config(
+        signature,
+        RpcTransactionConfig {
+            encoding: Some(UiTransactionEncoding::Json),
+            commitment: Some(CommitmentConfig::confirmed()),
+            max_supported_transaction_version: Some(0),
+        },
+    )?;
+    let logs = match transaction.transaction.meta {
+        Some(UiTransactionStatusMeta {
+            log_messages: OptionSerializer::Some(log_messages),
+            ..
+        }) => log_messages,
+        _ => Vec::new(),
+    };
+
+    let program_str = program_id.to_string();
+    let invoke_re = Regex::new(r"^Program (\S+) invoke \[\d+\]$").unwrap();
+    let return_re = Regex::new(r"^Program (\S+) (success|failed)").unwrap();
+    let mut program_stack: Vec<String> = Vec::new();
+    let mut events = Vec::new();
+    for log in logs.iter() {
+        if log.starts_with(PROGRAM_LOG) {
+            continue;
+        } else if let Some(data) = log.strip_prefix(PROGRAM_DATA) {
+            if program_stack.last() != Some(&program_str) {
+                continue;
+            }
+            let Ok(borsh_bytes) =
+                base64::Engine::decode(&base64::engine::general_purpose::STANDARD, data)
+            else {
+                continue;
+            };
+            if let Some(event) = RaydiumEvent::try_from_data(&borsh_bytes) {
+                events.push(event);
+            }
+        } else if let Some(c) = invoke_re.captures(log) {
+            program_stack.push(c[1].to_string());
+        } else if return_re.is_match(log) {
+            program_stack.pop();
+        }
+    }
+    Ok(events)
+}
+
 pub fn parse_program_event(
     self_program_str: &str,
     meta: Option<UiTransactionStatusMeta>,
-) -> Result<(), ClientError> {
+) -> Result<(), AnchorClientError> {
     let logs: Vec<String> = if let Some(meta_data) = meta {
         let log_messages = if let OptionSerializer::Some(log_messages) = meta_data.log_messages {
             log_messages
@@ -70,17 +213,17 @@ struct Execution {
 }
 
 impl Execution {
-    pub fn new(logs: &mut &[String]) -> Result<Self, ClientError> {
+    pub fn new(logs: &mut &[String]) -> Result<Self, AnchorClientError> {
         let l = &logs[0];
         *logs = &logs[1..];
 
         let re = Regex::new(r"^Program (.*) invoke.*$").unwrap();
         let c = re
             .captures(l)
-            .ok_or_else(|| ClientError::LogParseError(l.to_string()))?;
+            .ok_or_else(|| AnchorClientError::LogParseError(l.to_string()))?;
         let program = c
             .get(1)
-            .ok_or_else(|| ClientError::LogParseError(l.to_string()))?
+            .ok_or_else(|| AnchorClientError::LogParseError(l.to_string()))?
             .as_str()
             .to_string();
         Ok(Self {
@@ -111,7 +254,7 @@ pub fn handle_program_log(
     self_program_str: &str,
     l: &str,
     with_prefix: bool,
-) -> Result<(Option<String>, bool), ClientError> {
+) -> Result<(Option<String>, bool), AnchorClientError> {
     // Log emitted from the current program.
     if let Some(log) = if with_prefix {
         l.strip_prefix(PROGRAM_LOG)
@@ -132,62 +275,9 @@ pub fn handle_program_log(
                 }
             };
 
-        let mut slice: &[u8] = &borsh_bytes[..];
-        let disc: [u8; 8] = {
-            let mut disc = [0; 8];
-            disc.copy_from_slice(&borsh_bytes[..8]);
-            slice = &slice[8..];
-            disc
-        };
-        match &disc[..] {
-            ConfigChangeEvent::DISCRIMINATOR => {
-                println!("{:#?}", decode_event::<ConfigChangeEvent>(&mut slice)?);
-            }
-            CollectPersonalFeeEvent::DISCRIMINATOR => {
-                println!(
-                    "{:#?}",
-                    decode_event::<CollectPersonalFeeEvent>(&mut slice)?
-                );
-            }
-            CollectProtocolFeeEvent::DISCRIMINATOR => {
-                println!(
-                    "{:#?}",
-                    decode_event::<CollectProtocolFeeEvent>(&mut slice)?
-                );
-            }
-            CreatePersonalPositionEvent::DISCRIMINATOR => {
-                println!(
-                    "{:#?}",
-                    decode_event::<CreatePersonalPositionEvent>(&mut slice)?
-                );
-            }
-            DecreaseLiquidityEvent::DISCRIMINATOR => {
-                println!("{:#?}", decode_event::<DecreaseLiquidityEvent>(&mut slice)?);
-            }
-            IncreaseLiquidityEvent::DISCRIMINATOR => {
-                println!("{:#?}", decode_event::<IncreaseLiquidityEvent>(&mut slice)?);
-            }
-            LiquidityCalculateEvent::DISCRIMINATOR => {
-                println!(
-                    "{:#?}",
-                    decode_event::<LiquidityCalculateEvent>(&mut slice)?
-                );
-            }
-            LiquidityChangeEvent::DISCRIMINATOR => {
-                println!("{:#?}", decode_event::<LiquidityChangeEvent>(&mut slice)?);
-            }
-            // PriceChangeEvent::DISCRIMINATOR => {
-            //     println!("{:#?}", decode_event::<PriceChangeEvent>(&mut slice)?);
-            // }
-            SwapEvent::DISCRIMINATOR => {
-                println!("{:#?}", decode_event::<SwapEvent>(&mut slice)?);
-            }
-            PoolCreatedEvent::DISCRIMINATOR => {
-                println!("{:#?}", decode_event::<PoolCreatedEvent>(&mut slice)?);
-            }
-            _ => {
-                println!("unknow event: {}", l);
-            }
+        match decode_raydium_event(&borsh_bytes)? {
+            Some(event) => println!("{:#?}", event),
+            None => println!("unknow event: {}", l),
         }
         return Ok((None, false));
     } else {
@@ -213,9 +303,9 @@ fn handle_system_log(this_program_str: &str, log: &str) -> (Option<String>, bool
 
 fn decode_event<T: anchor_lang::Event + anchor_lang::AnchorDeserialize>(
     slice: &mut &[u8],
-) -> Result<T, ClientError> {
+) -> Result<T, AnchorClientError> {
     let event: T = anchor_lang::AnchorDeserialize::deserialize(slice)
-        .map_err(|e| ClientError::LogParseError(e.to_string()))?;
+        .map_err(|e| AnchorClientError::LogParseError(e.to_string()))?;
     Ok(event)
 }
 
@@ -223,7 +313,7 @@ pub fn parse_program_instruction(
     self_program_str: &str,
     encoded_transaction: EncodedTransaction,
     meta: Option<UiTransactionStatusMeta>,
-) -> Result<(), ClientError> {
+) -> Result<(), AnchorClientError> {
     let ui_raw_msg = match encoded_transaction {
         solana_transaction_status::EncodedTransaction::Json(ui_tx) => {
             let ui_message = ui_tx.message;
@@ -311,7 +401,7 @@ pub fn parse_program_instruction(
 pub fn handle_program_instruction(
     instr_data: &str,
     decode_type: InstructionDecodeType,
-) -> Result<(), ClientError> {
+) -> Result<(), AnchorClientError> {
     let data;
     match decode_type {
         InstructionDecodeType::BaseHex => {
@@ -784,3 +874,100 @@ fn decode_instruction<T: anchor_lang::AnchorDeserialize>(
         .map_err(|_| anchor_lang::error::ErrorCode::InstructionDidNotDeserialize)?;
     Ok(instruction)
 }
+
+/// The errors of the program in declaration order, i.e. ordered by code.
+const PROGRAM_ERRORS: &[ErrorCode] = &[
+    ErrorCode::LOK,
+    ErrorCode::NotApproved,
+    ErrorCode::InvalidUpdateConfigFlag,
+    ErrorCode::AccountLack,
+    ErrorCode::ClosePositionErr,
+    ErrorCode::ZeroMintAmount,
+    ErrorCode::InvalidTickIndex,
+    ErrorCode::TickInvalidOrder,
+    ErrorCode::TickLowerOverflow,
+    ErrorCode::TickUpperOverflow,
+    ErrorCode::TickAndSpacingNotMatch,
+    ErrorCode::InvalidTickArray,
+    ErrorCode::InvalidTickArrayBoundary,
+    ErrorCode::SqrtPriceLimitOverflow,
+    ErrorCode::SqrtPriceX64,
+    ErrorCode::LiquiditySubValueErr,
+    ErrorCode::LiquidityAddValueErr,
+    ErrorCode::InvalidLiquidity,
+    ErrorCode::ForbidBothZeroForSupplyLiquidity,
+    ErrorCode::LiquidityInsufficient,
+    ErrorCode::TransactionTooOld,
+    ErrorCode::PriceSlippageCheck,
+    ErrorCode::TooLittleOutputReceived,
+    ErrorCode::TooMuchInputPaid,
+    ErrorCode::ZeroAmountSpecified,
+    ErrorCode::InvalidInputPoolVault,
+    ErrorCode::TooSmallInputOrOutputAmount,
+    ErrorCode::NotEnoughTickArrayAccount,
+    ErrorCode::InvalidFirstTickArrayAccount,
+    ErrorCode::InvalidRewardIndex,
+    ErrorCode::FullRewardInfo,
+    ErrorCode::RewardTokenAlreadyInUse,
+    ErrorCode::ExceptRewardMint,
+    ErrorCode::InvalidRewardInitParam,
+    ErrorCode::InvalidRewardDesiredAmount,
+    ErrorCode::InvalidRewardInputAccountNumber,
+    ErrorCode::InvalidRewardPeriod,
+    ErrorCode::NotApproveUpdateRewardEmissiones,
+    ErrorCode::UnInitializedRewardInfo,
+    ErrorCode::NotSupportMint,
+    ErrorCode::MissingTickArrayBitmapExtensionAccount,
+    ErrorCode::InsufficientLiquidityForDirection,
+    ErrorCode::MaxTokenOverflow,
+    ErrorCode::CalculateOverflow,
+    ErrorCode::TransferFeeCalculateNotMatch,
+    ErrorCode::InvalidSupportMintAccountNumber,
+    ErrorCode::SupportMintAssociatedAlreadyInitialized,
+    ErrorCode::NotAllowedMintExtension,
+    ErrorCode::SupportMintInUse,
+];
+
+/// The message of the program error with the custom error `code`, e.g. `6021` decodes to
+/// "Price slippage check". Codes outside of the program's `ErrorCode` return `None`.
+pub fn decode_program_error(code: u32) -> Option<&'static str> {
+    static MESSAGES: OnceLock<Vec<(u32, String)>> = OnceLock::new();
+    MESSAGES
+        .get_or_init(|| {
+            PROGRAM_ERRORS
+                .iter()
+                .map(|error| (u32::from(*error), error.to_string()))
+                .collect()
+        })
+        .iter()
+        .find(|(error_code, _)| *error_code == code)
+        .map(|(_, message)| message.as_str())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn program_errors_complete_test() {
+        // anchor numbers the variants from 6000 in declaration order
+        for (i, error) in PROGRAM_ERRORS.iter().enumerate() {
+            assert_eq!(u32::from(*error), 6000 + i as u32, "{:?}", error);
+        }
+        assert_eq!(
+            u32::from(*PROGRAM_ERRORS.last().unwrap()),
+            u32::from(ErrorCode::SupportMintInUse)
+        );
+    }
+
+    #[test]
+    fn decode_program_error_test() {
+        let code = u32::from(ErrorCode::SupportMintInUse);
+        assert_eq!(
+            decode_program_error(code),
+            Some(ErrorCode::SupportMintInUse.to_string().as_str())
+        );
+        assert_eq!(decode_program_error(code + 1), None);
+        assert_eq!(decode_program_error(5999), None);
+    }
+}
@@ -0,0 +1,275 @@
+use anyhow::{anyhow, Result};
+use raydium_amm_v3::states::{
+    PersonalPositionState, PoolState, TickArrayState, TickState, REWARD_NUM,
+};
+use serde::Serialize;
+use solana_client::rpc_client::RpcClient;
+use solana_sdk::{clock::Clock, pubkey::Pubkey, sysvar};
+
+use super::super::{
+    compute_fees_owed, compute_rewards_owed, deserialize_anchor_account,
+    get_all_nft_and_position_by_owner, get_tick_array_key, liquidity_to_amounts, ClientError,
+};
+use super::amm_instructions::fetch_anchor_accounts;
+
+/// Output format of `export_positions`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ExportFormat {
+    Csv,
+    Json,
+}
+
+/// A position as exported by `export_positions`. Pubkeys are base58 and the liquidity is a
+/// decimal string, as u128 doesn't fit in a JSON number for most consumers.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize)]
+pub struct PositionExport {
+    pub position: String,
+    pub nft_mint: String,
+    pub pool_id: String,
+    pub token_mint_0: String,
+    pub token_mint_1: String,
+    pub tick_lower: i32,
+    pub tick_upper: i32,
+    pub liquidity: String,
+    pub amount_0: u64,
+    pub amount_1: u64,
+    pub fees_owed_0: u64,
+    pub fees_owed_1: u64,
+}
+
+const CSV_HEADER: &str = "position,nft_mint,pool_id,token_mint_0,token_mint_1,tick_lower,tick_upper,liquidity,amount_0,amount_1,fees_owed_0,fees_owed_1";
+
+/// Export the positions of `owner` with their pool, tick range, liquidity, the token amounts
+/// the liquidity is currently worth and the fees owed, including the fees accrued since the
+/// position was last updated on-chain. Amounts are raw token amounts.
+pub fn export_positions(
+    rpc_client: &RpcClient,
+    owner: &Pubkey,
+    program_id: &Pubkey,
+    format: ExportFormat,
+) -> Result<String, ClientError> {
+    let exports = collect_position_exports(rpc_client, owner, program_id)?;
+    match format {
+        ExportFormat::Json => Ok(serde_json::to_string_pretty(&exports)?),
+        ExportFormat::Csv => {
+            let mut csv = String::from(CSV_HEADER);
+            csv.push('\n');
+            for export in exports {
+                csv.push_str(&format!(
+                    "{},{},{},{},{},{},{},{},{},{},{},{}\n",
+                    export.position,
+                    export.nft_mint,
+                    export.pool_id,
+                    export.token_mint_0,
+                    export.token_mint_1,
+                    export.tick_lower,
+                    export.tick_upper,
+                    export.liquidity,
+                    export.amount_0,
+                    export.amount_1,
+                    export.fees_owed_0,
+                    export.fees_owed_1,
+                ));
+            }
+            Ok(csv)
+        }
+    }
+}
+
+/// The positions of an owner in a single pool, rolled up by `aggregate_positions_for_pool`.
+/// Amounts are raw token amounts, fees and rewards owed include what accrued since each
+/// position was last updated on-chain.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct PoolPositionSummary {
+    pub pool_id: Pubkey,
+    pub position_count: usize,
+    pub liquidity: u128,
+    /// The token0 and token1 amounts the liquidity is currently worth.
+    pub amount_0: u64,
+    pub amount_1: u64,
+    pub fees_owed_0: u64,
+    pub fees_owed_1: u64,
+    /// The mint of each reward, the default pubkey for uninitialized rewards.
+    pub reward_mints: [Pubkey; REWARD_NUM],
+    pub rewards_owed: [u64; REWARD_NUM],
+}
+
+/// Sum the liquidity, token exposure, fees owed and rewards owed of all the positions of
+/// `owner` in `pool_id`.
+pub fn aggregate_positions_for_pool(
+    rpc_client: &RpcClient,
+    owner: &Pubkey,
+    pool_id: &Pubkey,
+    program_id: &Pubkey,
+) -> Result<PoolPositionSummary, ClientError> {
+    let pool_account = rpc_client.get_account(pool_id)?;
+    if pool_account.owner != *program_id {
+        return Err(anyhow!("pool {} is not owned by program {}", pool_id, program_id).into());
+    }
+    let pool_state = deserialize_anchor_account::<PoolState>(&pool_account)?;
+    let clock: Clock = bincode::deserialize(&rpc_client.get_account(&sysvar::clock::id())?.data)?;
+    let reward_infos = pool_state.reward_infos;
+    let mut summary = PoolPositionSummary {
+        pool_id: *pool_id,
+        position_count: 0,
+        liquidity: 0,
+        amount_0: 0,
+        amount_1: 0,
+        fees_owed_0: 0,
+        fees_owed_1: 0,
+        reward_mints: reward_infos.map(|reward_info| reward_info.token_mint),
+        rewards_owed: [0; REWARD_NUM],
+    };
+    for position in load_positions(rpc_client, owner, program_id, Some(pool_id))? {
+        let personal_position = &position.personal_position;
+        let (amount_0, amount_1) = liquidity_to_amounts(
+            pool_state.sqrt_price_x64,
+            personal_position.tick_lower_index,
+            personal_position.tick_upper_index,
+            personal_position.liquidity,
+        )?;
+        let (fees_owed_0, fees_owed_1) = compute_fees_owed(
+            &pool_state,
+            personal_position,
+            &position.tick_lower,
+            &position.tick_upper,
+        );
+        let rewards_owed = compute_rewards_owed(
+            &pool_state,
+            personal_position,
+            &position.tick_lower,
+            &position.tick_upper,
+            clock.unix_timestamp as u64,
+        )?;
+        summary.position_count += 1;
+        summary.liquidity = summary
+            .liquidity
+            .saturating_add(personal_position.liquidity);
+        summary.amount_0 = summary.amount_0.saturating_add(amount_0);
+        summary.amount_1 = summary.amount_1.saturating_add(amount_1);
+        summary.fees_owed_0 = summary.fees_owed_0.saturating_add(fees_owed_0);
+        summary.fees_owed_1 = summary.fees_owed_1.saturating_add(fees_owed_1);
+        for (total, owed) in summary.rewards_owed.iter_mut().zip(rewards_owed) {
+            *total = total.saturating_add(owed);
+        }
+    }
+    Ok(summary)
+}
+
+/// A position of the owner with its pool and the tick states bounding its range.
+struct LoadedPosition {
+    position: Pubkey,
+    personal_position: PersonalPositionState,
+    pool_state: PoolState,
+    tick_lower: TickState,
+    tick_upper: TickState,
+}
+
+/// Load the positions of `owner`, only those in `pool_id` when set, in the order returned by
+/// `get_all_nft_and_position_by_owner`.
+fn load_positions(
+    rpc_client: &RpcClient,
+    owner: &Pubkey,
+    program_id: &Pubkey,
+    pool_id: Option<&Pubkey>,
+) -> Result<Vec<LoadedPosition>> {
+    let positions = get_all_nft_and_position_by_owner(rpc_client, owner, program_id)?;
+    let position_keys = positions.iter().map(|p| p.position).collect::<Vec<_>>();
+    let mut personal_positions =
+        fetch_anchor_accounts::<PersonalPositionState>(rpc_client, &position_keys)?;
+    if let Some(pool_id) = pool_id {
+        personal_positions.retain(|_, personal_position| personal_position.pool_id == *pool_id);
+    }
+    let pool_keys = personal_positions
+        .values()
+        .map(|p| p.pool_id)
+        .collect::<Vec<_>>();
+    let pools = fetch_anchor_accounts::<PoolState>(rpc_client, &pool_keys)?;
+
+    let tick_array_key = |personal_position: &PersonalPositionState, tick_index: i32| {
+        let tick_spacing = pools[&personal_position.pool_id].tick_spacing;
+        let start_index = TickArrayState::get_array_start_index(tick_index, tick_spacing);
+        get_tick_array_key(program_id, &personal_position.pool_id, start_index)
+    };
+    let mut tick_array_keys = Vec::new();
+    for personal_position in personal_positions.values() {
+        tick_array_keys.push(tick_array_key(
+            personal_position,
+            personal_position.tick_lower_index,
+        ));
+        tick_array_keys.push(tick_array_key(
+            personal_position,
+            personal_position.tick_upper_index,
+        ));
+    }
+    let tick_arrays = fetch_anchor_accounts::<TickArrayState>(rpc_client, &tick_array_keys)?;
+
+    let mut loaded_positions = Vec::with_capacity(personal_positions.len());
+    for position in positions.iter() {
+        let Some(personal_position) = personal_positions.get(&position.position) else {
+            continue;
+        };
+        let pool_state = &pools[&personal_position.pool_id];
+        let tick_spacing = pool_state.tick_spacing;
+        let tick_lower_index = personal_position.tick_lower_index;
+        let tick_upper_index = personal_position.tick_upper_index;
+        let tick_lower = *tick_arrays[&tick_array_key(personal_position, tick_lower_index)]
+            .clone()
+            .get_tick_state_mut(tick_lower_index, tick_spacing)
+            .map_err(|e| anyhow!("{}", e))?;
+        let tick_upper = *tick_arrays[&tick_array_key(personal_position, tick_upper_index)]
+            .clone()
+            .get_tick_state_mut(tick_upper_index, tick_spacing)
+            .map_err(|e| anyhow!("{}", e))?;
+        loaded_positions.push(LoadedPosition {
+            position: position.position,
+            personal_position: personal_position.clone(),
+            pool_state: *pool_state,
+            tick_lower,
+            tick_upper,
+        });
+    }
+    Ok(loaded_positions)
+}
+
+fn collect_position_exports(
+    rpc_client: &RpcClient,
+    owner: &Pubkey,
+    program_id: &Pubkey,
+) -> Result<Vec<PositionExport>> {
+    let positions = load_positions(rpc_client, owner, program_id, None)?;
+    let mut exports = Vec::with_capacity(positions.len());
+    for position in positions.iter() {
+        let personal_position = &position.personal_position;
+        let pool_state = &position.pool_state;
+        let tick_lower_index = personal_position.tick_lower_index;
+        let tick_upper_index = personal_position.tick_upper_index;
+        let (amount_0, amount_1) = liquidity_to_amounts(
+            pool_state.sqrt_price_x64,
+            tick_lower_index,
+            tick_upper_index,
+            personal_position.liquidity,
+        )?;
+        let (fees_owed_0, fees_owed_1) = compute_fees_owed(
+            pool_state,
+            personal_position,
+            &position.tick_lower,
+            &position.tick_upper,
+        );
+        exports.push(PositionExport {
+            position: position.position.to_string(),
+            nft_mint: personal_position.nft_mint.to_string(),
+            pool_id: personal_position.pool_id.to_string(),
+            token_mint_0: pool_state.token_mint_0.to_string(),
+            token_mint_1: pool_state.token_mint_1.to_string(),
+            tick_lower: tick_lower_index,
+            tick_upper: tick_upper_index,
+            liquidity: personal_position.liquidity.to_string(),
+            amount_0,
+            amount_1,
+            fees_owed_0,
+            fees_owed_1,
+        });
+    }
+    Ok(exports)
+}
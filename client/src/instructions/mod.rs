@@ -1,12 +1,16 @@
 pub mod amm_instructions;
 pub mod events_instructions_parse;
+pub mod export;
 pub mod rpc;
+pub mod subscribe;
 pub mod token_instructions;
 pub mod utils;
 
 // Re-export commonly used functions from submodules
 pub use amm_instructions::*;
 pub use events_instructions_parse::*;
+pub use export::*;
 pub use rpc::*;
+pub use subscribe::*;
 pub use token_instructions::*;
 pub use utils::*;
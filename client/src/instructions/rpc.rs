@@ -1,15 +1,42 @@
 use anyhow::{anyhow, Result};
+use rand::Rng;
+use raydium_amm_v3::states::{
+    ObservationState, PersonalPositionState, PoolState, ProtocolPositionState,
+    TickArrayBitmapExtension, TickArrayState,
+};
 use solana_client::{
+    client_error::{ClientError as RpcClientError, ClientErrorKind},
     rpc_client::RpcClient,
     rpc_config::RpcSendTransactionConfig,
-    rpc_request::RpcRequest,
+    rpc_request::{RpcError, RpcRequest, RpcResponseErrorData},
     rpc_response::{RpcResult, RpcSimulateTransactionResult},
 };
 use solana_sdk::{
-    account::Account, commitment_config::CommitmentConfig, program_pack::Pack as TokenPack,
-    pubkey::Pubkey, signature::Signature, transaction::Transaction,
+    account::Account,
+    commitment_config::CommitmentConfig,
+    compute_budget::{self, ComputeBudgetInstruction},
+    hash::Hash,
+    instruction::{AccountMeta, Instruction},
+    message::Message,
+    program_pack::Pack as TokenPack,
+    pubkey::Pubkey,
+    signature::{Keypair, Signature, Signer},
+    transaction::{Transaction, TransactionError},
+};
+use solana_transaction_status::{
+    TransactionConfirmationStatus, TransactionStatus as RpcTransactionStatus,
+};
+use spl_token_2022::{
+    extension::{BaseStateWithExtensions, ExtensionType, StateWithExtensions},
+    state::{Account as TokenAccount, Mint},
 };
 use std::convert::Into;
+use std::time::{Duration, Instant};
+
+use super::super::{
+    error::{program_error, slippage_error},
+    mint_token_program, ClientConfig, ClientError,
+};
 
 pub fn simulate_transaction(
     client: &RpcClient,
@@ -26,7 +53,11 @@ pub fn simulate_transaction(
     )
 }
 
-pub fn send_txn(client: &RpcClient, txn: &Transaction, wait_confirm: bool) -> Result<Signature> {
+pub fn send_txn(
+    client: &RpcClient,
+    txn: &Transaction,
+    wait_confirm: bool,
+) -> Result<Signature, ClientError> {
     Ok(client.send_and_confirm_transaction_with_spinner_and_config(
         txn,
         if wait_confirm {
@@ -41,17 +72,471 @@ pub fn send_txn(client: &RpcClient, txn: &Transaction, wait_confirm: bool) -> Re
     )?)
 }
 
-pub fn get_token_account<T: TokenPack>(client: &RpcClient, addr: &Pubkey) -> Result<T> {
+/// Send the transaction built by `tx_builder` for a recent blockhash and signed by `signers`,
+/// and wait for its confirmation. When the blockhash expires before the transaction lands,
+/// e.g. because a hardware wallet took too long to sign, the transaction is rebuilt with a
+/// fresh blockhash and signed again, up to `attempts` times in total.
+pub fn send_and_confirm_with_refresh(
+    client: &RpcClient,
+    tx_builder: impl Fn(Hash) -> Transaction,
+    signers: &[&dyn Signer],
+    attempts: usize,
+) -> Result<Signature, ClientError> {
+    let mut attempt = 0;
+    loop {
+        attempt += 1;
+        let recent_blockhash = client.get_latest_blockhash()?;
+        let mut transaction = tx_builder(recent_blockhash);
+        transaction.try_sign(signers, recent_blockhash)?;
+        let result = send_txn(client, &transaction, true);
+        let expired =
+            matches!(&result, Err(ClientError::RpcError(err)) if is_blockhash_expired(err));
+        if !expired || attempt >= attempts {
+            return result;
+        }
+    }
+}
+
+/// The blockhash was unknown to the node, or expired before the transaction was confirmed.
+fn is_blockhash_expired(err: &RpcClientError) -> bool {
+    if err.get_transaction_error() == Some(TransactionError::BlockhashNotFound) {
+        return true;
+    }
+    matches!(
+        err.kind(),
+        ClientErrorKind::RpcError(RpcError::ForUser(message))
+            if message.starts_with("unable to confirm transaction")
+    )
+}
+
+/// Interval between two signature status polls of `confirm_transaction`.
+const CONFIRM_POLL_INTERVAL: Duration = Duration::from_millis(500);
+
+/// Status of a transaction that reached the confirmed commitment, see `confirm_transaction`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct TransactionStatus {
+    pub slot: u64,
+    /// Either `Confirmed` or `Finalized`.
+    pub confirmation_status: TransactionConfirmationStatus,
+    /// The error the transaction failed with, it was still included in the block and charged
+    /// its fee.
+    pub err: Option<TransactionError>,
+}
+
+/// Poll the status of the transaction `signature` until it is confirmed or finalized, and
+/// return it. Fail once `timeout` elapses, e.g. when the transaction was dropped or its
+/// blockhash expired.
+pub fn confirm_transaction(
+    client: &RpcClient,
+    signature: &Signature,
+    timeout: Duration,
+) -> Result<TransactionStatus, ClientError> {
+    confirm_transaction_with_progress(client, signature, timeout, |_| {})
+}
+
+/// Same as `confirm_transaction`, calling `on_progress` with the status returned by each poll,
+/// `None` while the transaction isn't known to the node, so that interactive callers can show
+/// the progress of the confirmation.
+pub fn confirm_transaction_with_progress(
+    client: &RpcClient,
+    signature: &Signature,
+    timeout: Duration,
+    mut on_progress: impl FnMut(Option<&RpcTransactionStatus>),
+) -> Result<TransactionStatus, ClientError> {
+    let start = Instant::now();
+    let mut last_status = None;
+    loop {
+        let status = client
+            .get_signature_statuses(&[*signature])?
+            .value
+            .into_iter()
+            .next()
+            .flatten();
+        on_progress(status.as_ref());
+        if let Some(status) = &status {
+            let confirmation_status = status.confirmation_status();
+            if confirmation_status != TransactionConfirmationStatus::Processed {
+                return Ok(TransactionStatus {
+                    slot: status.slot,
+                    confirmation_status,
+                    err: status.err.clone(),
+                });
+            }
+        }
+        last_status = status.or(last_status);
+        if start.elapsed() >= timeout {
+            return Err(match last_status {
+                Some(status) => anyhow!(
+                    "transaction {} not confirmed within {:?}, last processed at slot {}",
+                    signature,
+                    timeout,
+                    status.slot
+                ),
+                None => anyhow!("transaction {} not found within {:?}", signature, timeout),
+            }
+            .into());
+        }
+        std::thread::sleep(CONFIRM_POLL_INTERVAL);
+    }
+}
+
+/// Result of `send_or_build`.
+#[derive(Debug)]
+pub enum TxOutcome {
+    Sent(Signature),
+    /// The transaction built in dry-run mode, it can be serialized with `bincode` for
+    /// offline signing.
+    DryRun(Transaction),
+}
+
+/// Build a transaction of `instructions` with a recent blockhash, signed by `signers`.
+/// Signers missing from `signers` leave their signature empty, so an empty `signers` builds
+/// an unsigned transaction for offline signing, e.g. with a hardware wallet.
+pub fn build_transaction(
+    client: &RpcClient,
+    instructions: &[Instruction],
+    payer: &Pubkey,
+    signers: &[&Keypair],
+) -> Result<Transaction, ClientError> {
+    let recent_blockhash = client.get_latest_blockhash()?;
+    let mut transaction = Transaction::new_unsigned(Message::new(instructions, Some(payer)));
+    transaction.try_partial_sign(signers, recent_blockhash)?;
+    Ok(transaction)
+}
+
+/// Build the transaction of `instructions`, with the compute budget instructions of `config`
+/// prepended, and send it, or return it without sending when `build_only` is set, e.g. for a
+/// `--dry-run` flag. The payer must hold the transaction fee, rent of the accounts created by
+/// `instructions` isn't accounted for, see `ensure_sufficient_sol` and the `estimate_rent_for_*`
+/// functions.
+pub fn send_or_build(
+    client: &RpcClient,
+    config: &ClientConfig,
+    instructions: &[Instruction],
+    payer: &Pubkey,
+    signers: &[&Keypair],
+    build_only: bool,
+    wait_confirm: bool,
+) -> Result<TxOutcome, ClientError> {
+    let mut instructions = instructions.to_vec();
+    config.apply_compute_budget(&mut instructions);
+    let transaction = build_transaction(client, &instructions, payer, signers)?;
+    if build_only {
+        return Ok(TxOutcome::DryRun(transaction));
+    }
+    ensure_sufficient_sol(
+        client,
+        payer,
+        client.get_fee_for_message(transaction.message())?,
+    )?;
+    let signature = send_txn(client, &transaction, wait_confirm)?;
+    Ok(TxOutcome::Sent(signature))
+}
+
+/// Fail with `ClientError::InsufficientSol` when `payer` holds less than `lamports_needed`,
+/// before sending a transaction that would fail on it.
+pub fn ensure_sufficient_sol(
+    client: &RpcClient,
+    payer: &Pubkey,
+    lamports_needed: u64,
+) -> Result<(), ClientError> {
+    let available = client.get_balance(payer)?;
+    if available < lamports_needed {
+        return Err(ClientError::InsufficientSol {
+            payer: *payer,
+            needed: lamports_needed,
+            available,
+        });
+    }
+    Ok(())
+}
+
+/// Size of a metaplex metadata account, `MAX_METADATA_LEN` of the token metadata program.
+const METADATA_ACCOUNT_LEN: usize = 679;
+/// Size of the token metadata extension of a Token-2022 position nft mint: the TLV header, the
+/// update authority and mint, the name, symbol and uri set by the program, whose uri ends with
+/// the personal position key of at most 44 base58 chars, and no additional metadata.
+const TOKEN_2022_NFT_METADATA_LEN: usize = 4 + 32 + 32 + (4 + 30) + (4 + 3) + (4 + 49 + 44) + 4;
+
+/// Lamports opening a position spends on rent: the position nft mint and token account, the
+/// personal and protocol positions and, `with_metadata`, the nft metadata. `nft_token_program`
+/// is the token program of the nft mint, a Token-2022 nft mint carries the mint close authority
+/// extension and holds its metadata itself, see `open_position_with_token22_nft_instr`. Tick
+/// arrays the position initializes cost `estimate_rent_for_tick_arrays` on top. Add the
+/// transaction fee to get the lamports to check with `ensure_sufficient_sol`.
+pub fn estimate_rent_for_open_position(
+    client: &RpcClient,
+    nft_token_program: &Pubkey,
+    with_metadata: bool,
+) -> Result<u64, ClientError> {
+    let mut account_lens = vec![PersonalPositionState::LEN, ProtocolPositionState::LEN];
+    if *nft_token_program == spl_token_2022::id() {
+        let mut mint_extensions = vec![ExtensionType::MintCloseAuthority];
+        if with_metadata {
+            mint_extensions.push(ExtensionType::MetadataPointer);
+        }
+        let mut mint_len = ExtensionType::try_calculate_account_len::<Mint>(&mint_extensions)?;
+        if with_metadata {
+            mint_len += TOKEN_2022_NFT_METADATA_LEN;
+        }
+        account_lens.push(mint_len);
+        // associated token accounts of Token-2022 mints are created with an immutable owner
+        account_lens.push(ExtensionType::try_calculate_account_len::<TokenAccount>(
+            &[ExtensionType::ImmutableOwner],
+        )?);
+    } else {
+        account_lens.push(spl_token::state::Mint::LEN);
+        account_lens.push(spl_token::state::Account::LEN);
+        if with_metadata {
+            account_lens.push(METADATA_ACCOUNT_LEN);
+        }
+    }
+    rent_lamports(client, &account_lens)
+}
+
+/// Lamports `create_pool` spends on rent: the pool, its observation and tick array bitmap
+/// extension accounts, and both vaults, sized for the extensions of their mint.
+pub fn estimate_rent_for_create_pool(
+    client: &RpcClient,
+    token_mint_0: &Pubkey,
+    token_mint_1: &Pubkey,
+) -> Result<u64, ClientError> {
+    let mint_keys = [*token_mint_0, *token_mint_1];
+    let mut account_lens = vec![
+        PoolState::LEN,
+        ObservationState::LEN,
+        TickArrayBitmapExtension::LEN,
+    ];
+    for (mint, account) in mint_keys
+        .iter()
+        .zip(get_multiple_accounts_chunked(client, &mint_keys)?)
+    {
+        let account = account.ok_or_else(|| anyhow!("mint {} not found", mint))?;
+        account_lens.push(vault_account_len(mint, &account)?);
+    }
+    rent_lamports(client, &account_lens)
+}
+
+/// Lamports spent on rent by initializing `count` tick arrays, e.g. by a position whose ticks
+/// fall in tick arrays not initialized yet.
+pub fn estimate_rent_for_tick_arrays(client: &RpcClient, count: usize) -> Result<u64, ClientError> {
+    Ok(rent_lamports(client, &[TickArrayState::LEN])? * count as u64)
+}
+
+/// Size of a token account of `mint`, with the account extensions its mint extensions require.
+fn vault_account_len(mint: &Pubkey, mint_account: &Account) -> Result<usize, ClientError> {
+    if mint_token_program(mint, mint_account)? == spl_token::id() {
+        return Ok(spl_token::state::Account::LEN);
+    }
+    let mint_state = StateWithExtensions::<Mint>::unpack(&mint_account.data)?;
+    let required_extensions =
+        ExtensionType::get_required_init_account_extensions(&mint_state.get_extension_types()?);
+    let account_len =
+        ExtensionType::try_calculate_account_len::<TokenAccount>(&required_extensions)?;
+    Ok(account_len)
+}
+
+fn rent_lamports(client: &RpcClient, account_lens: &[usize]) -> Result<u64, ClientError> {
+    let mut lamports = 0u64;
+    for account_len in account_lens {
+        lamports += client.get_minimum_balance_for_rent_exemption(*account_len)?;
+    }
+    Ok(lamports)
+}
+
+pub fn get_token_account<T: TokenPack>(
+    client: &RpcClient,
+    addr: &Pubkey,
+) -> Result<T, ClientError> {
     let account = client
         .get_account_with_commitment(addr, CommitmentConfig::processed())?
         .value
         .map_or(Err(anyhow!("Account not found")), Ok)?;
-    T::unpack_from_slice(&account.data).map_err(Into::into)
+    T::unpack_from_slice(&account.data).map_err(|e| ClientError::DeserializeError(e.into()))
 }
 
 pub fn get_multiple_accounts(
     client: &RpcClient,
     pubkeys: &[Pubkey],
-) -> Result<Vec<Option<Account>>> {
+) -> Result<Vec<Option<Account>>, ClientError> {
     Ok(client.get_multiple_accounts(pubkeys)?)
 }
+
+/// Max number of accounts a single `getMultipleAccounts` request accepts.
+pub const MAX_MULTIPLE_ACCOUNTS: usize = 100;
+
+/// Same as `get_multiple_accounts`, but splits `keys` into requests of at most
+/// `MAX_MULTIPLE_ACCOUNTS` keys. Results are returned in the order of `keys`.
+pub fn get_multiple_accounts_chunked(
+    client: &RpcClient,
+    keys: &[Pubkey],
+) -> Result<Vec<Option<Account>>, ClientError> {
+    get_multiple_accounts_chunked_with_commitment(client, keys, client.commitment())
+}
+
+/// Same as `get_multiple_accounts_chunked`, fetching at the given commitment.
+pub fn get_multiple_accounts_chunked_with_commitment(
+    client: &RpcClient,
+    keys: &[Pubkey],
+    commitment: CommitmentConfig,
+) -> Result<Vec<Option<Account>>, ClientError> {
+    let mut accounts = Vec::with_capacity(keys.len());
+    for chunk in keys.chunks(MAX_MULTIPLE_ACCOUNTS) {
+        accounts.extend(
+            client
+                .get_multiple_accounts_with_commitment(chunk, commitment)?
+                .value,
+        );
+    }
+    Ok(accounts)
+}
+
+/// Estimate the serialized size in bytes of a legacy transaction holding `instructions`,
+/// signed by `signers` signers. Signers that aren't referenced by any instruction, e.g. the
+/// fee payer, are counted as additional account keys.
+pub fn estimate_tx_size(instructions: &[Instruction], signers: usize) -> usize {
+    let message = Message::new(instructions, None);
+    let required_signatures = message.header.num_required_signatures as usize;
+    let signatures = signers.max(required_signatures);
+    let message_size = bincode::serialized_size(&message).unwrap() as usize;
+    // signatures are prefixed by their compact-u16 encoded count
+    let signatures_len_size = if signatures < 0x80 { 1 } else { 2 };
+    signatures_len_size + signatures * 64 + message_size + (signatures - required_signatures) * 32
+}
+
+/// Recommend a priority fee in micro-lamports per compute unit from the recent
+/// prioritization fees paid to write lock `accounts`, using the 75th percentile.
+pub fn estimate_priority_fee(client: &RpcClient, accounts: &[Pubkey]) -> Result<u64, ClientError> {
+    let mut fees = client
+        .get_recent_prioritization_fees(accounts)?
+        .into_iter()
+        .map(|fee| fee.prioritization_fee)
+        .collect::<Vec<u64>>();
+    if fees.is_empty() {
+        return Ok(0);
+    }
+    fees.sort_unstable();
+    let index = (fees.len() * 75).div_ceil(100).saturating_sub(1);
+    Ok(fees[index])
+}
+
+/// Prepend the compute unit limit and price instructions to `instructions`.
+pub fn with_priority_fee(instructions: &mut Vec<Instruction>, micro_lamports: u64, cu_limit: u32) {
+    instructions.splice(
+        0..0,
+        [
+            ComputeBudgetInstruction::set_compute_unit_limit(cu_limit),
+            ComputeBudgetInstruction::set_compute_unit_price(micro_lamports),
+        ],
+    );
+}
+
+/// The max compute units a transaction may request.
+pub const MAX_COMPUTE_UNIT_LIMIT: u32 = 1_400_000;
+
+/// Simulate `transaction` and set its compute unit limit to the units consumed plus
+/// `buffer_pct` percent, replacing any existing limit instruction. The rebuilt
+/// transaction is signed by `payer`, other signers must sign it again afterwards.
+pub fn simulate_and_set_cu_limit(
+    client: &RpcClient,
+    transaction: &mut Transaction,
+    payer: &Keypair,
+    buffer_pct: u8,
+) -> Result<u32, ClientError> {
+    let simulate_result =
+        simulate_transaction(client, transaction, false, CommitmentConfig::processed())?.value;
+    if let Some(err) = simulate_result.err {
+        if let Some(slippage_error) = slippage_error(&err) {
+            return Err(ClientError::SlippageExceeded(slippage_error));
+        }
+        let reason = program_error(&err).map_or_else(|| err.to_string(), |e| e.to_string());
+        return Err(anyhow!(
+            "simulate transaction failed: {}, logs: {:#?}",
+            reason,
+            simulate_result.logs.unwrap_or_default()
+        )
+        .into());
+    }
+    let units_consumed = simulate_result
+        .units_consumed
+        .ok_or_else(|| anyhow!("simulate transaction did not report units consumed"))?;
+    let cu_limit = (units_consumed * (100 + buffer_pct as u64) / 100)
+        .min(MAX_COMPUTE_UNIT_LIMIT as u64) as u32;
+
+    let message = &transaction.message;
+    let set_cu_limit_discriminator = ComputeBudgetInstruction::set_compute_unit_limit(0).data[0];
+    let mut instructions = vec![ComputeBudgetInstruction::set_compute_unit_limit(cu_limit)];
+    for compiled_instruction in message.instructions.iter() {
+        let program_id = *compiled_instruction.program_id(&message.account_keys);
+        if program_id == compute_budget::id()
+            && compiled_instruction.data.first() == Some(&set_cu_limit_discriminator)
+        {
+            continue;
+        }
+        instructions.push(Instruction {
+            program_id,
+            accounts: compiled_instruction
+                .accounts
+                .iter()
+                .map(|index| {
+                    let index = *index as usize;
+                    AccountMeta {
+                        pubkey: message.account_keys[index],
+                        is_signer: message.is_signer(index),
+                        is_writable: message.is_maybe_writable(index, None),
+                    }
+                })
+                .collect(),
+            data: compiled_instruction.data.clone(),
+        });
+    }
+    let recent_blockhash = message.recent_blockhash;
+    let fee_payer = message.account_keys.first().copied();
+    *transaction = Transaction::new_unsigned(Message::new(&instructions, fee_payer.as_ref()));
+    transaction.try_partial_sign(&[payer], recent_blockhash)?;
+    Ok(cu_limit)
+}
+
+/// Call `f` up to `attempts` times, backing off exponentially with jitter from `base_delay`
+/// between attempts. Stop early on errors that won't succeed on retry, and return the last
+/// error if all attempts fail.
+pub fn with_retry<T>(
+    attempts: usize,
+    base_delay: Duration,
+    mut f: impl FnMut() -> Result<T, ClientError>,
+) -> Result<T, ClientError> {
+    let mut attempt = 0;
+    loop {
+        match f() {
+            Ok(value) => return Ok(value),
+            Err(err) => {
+                attempt += 1;
+                if attempt >= attempts || !is_retryable_error(&err) {
+                    return Err(err);
+                }
+                let delay = base_delay.saturating_mul(1 << (attempt - 1).min(16));
+                let jitter = rand::rng().random_range(0..=delay.as_millis() as u64 / 2);
+                std::thread::sleep(delay + Duration::from_millis(jitter));
+            }
+        }
+    }
+}
+
+/// Transport failures, rate limits and unhealthy nodes are retryable, errors such as a missing
+/// account or a failed transaction are not.
+fn is_retryable_error(err: &ClientError) -> bool {
+    let ClientError::RpcError(err) = err else {
+        return false;
+    };
+    match err.kind() {
+        ClientErrorKind::Io(_)
+        | ClientErrorKind::Reqwest(_)
+        | ClientErrorKind::Middleware(_)
+        | ClientErrorKind::RpcError(RpcError::RpcRequestError(_)) => true,
+        ClientErrorKind::RpcError(RpcError::RpcResponseError {
+            data: RpcResponseErrorData::NodeUnhealthy { .. },
+            ..
+        }) => true,
+        _ => false,
+    }
+}
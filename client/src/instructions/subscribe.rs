@@ -0,0 +1,87 @@
+use anyhow::Result;
+use futures::channel::mpsc;
+use futures::{Stream, StreamExt};
+use raydium_amm_v3::states::PoolState;
+use solana_account_decoder::UiAccountEncoding;
+use solana_client::{nonblocking::pubsub_client::PubsubClient, rpc_config::RpcAccountInfoConfig};
+use solana_sdk::{account::Account, commitment_config::CommitmentConfig, pubkey::Pubkey};
+use std::time::Duration;
+
+use super::super::ClientError;
+use super::utils::deserialize_anchor_account;
+
+/// Delay before reconnecting after the websocket subscription dropped.
+pub const RECONNECT_DELAY: Duration = Duration::from_secs(1);
+
+/// Subscribe to the account notifications of a pool and yield each update as a `PoolState`.
+/// The subscription is re-established whenever the websocket drops, and stops once the
+/// returned stream is dropped. Must be called within a tokio runtime.
+pub async fn subscribe_pool_state(
+    ws_url: &str,
+    pool_id: Pubkey,
+) -> Result<impl Stream<Item = PoolState>, ClientError> {
+    // connect once up front so that a bad url is reported to the caller
+    let pubsub_client = PubsubClient::new(ws_url).await?;
+    let ws_url = ws_url.to_string();
+    let (sender, receiver) = mpsc::unbounded();
+    tokio::spawn(async move {
+        let config = RpcAccountInfoConfig {
+            encoding: Some(UiAccountEncoding::Base64),
+            commitment: Some(CommitmentConfig::confirmed()),
+            ..RpcAccountInfoConfig::default()
+        };
+        let mut connected = Some(pubsub_client);
+        loop {
+            let pubsub_client = match connected.take() {
+                Some(pubsub_client) => pubsub_client,
+                None => match PubsubClient::new(&ws_url).await {
+                    Ok(pubsub_client) => pubsub_client,
+                    Err(e) => {
+                        log::warn!(target: "raydium_client", "reconnect to {} failed: {}", ws_url, e);
+                        tokio::time::sleep(RECONNECT_DELAY).await;
+                        continue;
+                    }
+                },
+            };
+            let (mut notifications, unsubscribe) = match pubsub_client
+                .account_subscribe(&pool_id, Some(config.clone()))
+                .await
+            {
+                Ok(subscription) => subscription,
+                Err(e) => {
+                    log::warn!(target: "raydium_client", "subscribe to pool {} failed: {}", pool_id, e);
+                    tokio::time::sleep(RECONNECT_DELAY).await;
+                    continue;
+                }
+            };
+            while let Some(response) = notifications.next().await {
+                let Some(account) = response.value.decode::<Account>() else {
+                    log::warn!(target: "raydium_client", "skip undecodable update of pool {}", pool_id);
+                    continue;
+                };
+                let pool_state = match deserialize_anchor_account::<PoolState>(&account) {
+                    Ok(pool_state) => pool_state,
+                    Err(e) => {
+                        log::warn!(target: "raydium_client", "skip invalid update of pool {}: {}", pool_id, e);
+                        continue;
+                    }
+                };
+                if sender.unbounded_send(pool_state).is_err() {
+                    // the stream was dropped, nobody listens anymore
+                    drop(notifications);
+                    unsubscribe().await;
+                    return;
+                }
+            }
+            drop(notifications);
+            unsubscribe().await;
+            log::warn!(
+                target: "raydium_client",
+                "subscription of pool {} dropped, reconnecting",
+                pool_id
+            );
+            tokio::time::sleep(RECONNECT_DELAY).await;
+        }
+    });
+    Ok(receiver)
+}
@@ -1,7 +1,8 @@
-use super::super::{read_keypair_file, ClientConfig};
+use super::super::{
+    build_rpc_client, get_transfer_fee, read_keypair_file, ClientConfig, ClientError,
+};
 use anchor_client::{Client, Cluster};
 use anyhow::Result;
-use solana_client::rpc_client::RpcClient;
 use solana_sdk::{
     account::WritableAccount,
     instruction::Instruction,
@@ -11,7 +12,9 @@ use solana_sdk::{
     system_instruction,
 };
 use spl_token_2022::{
-    extension::{BaseStateWithExtensions, ExtensionType, StateWithExtensionsMut},
+    extension::{
+        BaseStateWithExtensions, ExtensionType, StateWithExtensions, StateWithExtensionsMut,
+    },
     state::{Account, Mint},
 };
 use spl_token_client::token::ExtensionInitializationParams;
@@ -25,7 +28,7 @@ pub fn create_and_init_mint_instr(
     freeze_authority: Option<&Pubkey>,
     extension_init_params: Vec<ExtensionInitializationParams>,
     decimals: u8,
-) -> Result<Vec<Instruction>> {
+) -> Result<Vec<Instruction>, ClientError> {
     let payer = read_keypair_file(&config.payer_path)?;
     let url = Cluster::Custom(config.http_url.clone(), config.ws_url.clone());
     // Client.
@@ -68,7 +71,7 @@ pub fn create_account_rent_exmpt_instr(
     new_account_key: &Pubkey,
     owner: Pubkey,
     data_size: usize,
-) -> Result<Vec<Instruction>> {
+) -> Result<Vec<Instruction>, ClientError> {
     let payer = read_keypair_file(&config.payer_path)?;
     let url = Cluster::Custom(config.http_url.clone(), config.ws_url.clone());
     // Client.
@@ -94,7 +97,7 @@ pub fn create_ata_token_account_instr(
     token_program: Pubkey,
     mint: &Pubkey,
     owner: &Pubkey,
-) -> Result<Vec<Instruction>> {
+) -> Result<Vec<Instruction>, ClientError> {
     let payer = read_keypair_file(&config.payer_path)?;
     let url = Cluster::Custom(config.http_url.clone(), config.ws_url.clone());
     // Client.
@@ -119,10 +122,10 @@ pub fn create_and_init_auxiliary_token(
     new_account_key: &Pubkey,
     mint: &Pubkey,
     owner: &Pubkey,
-) -> Result<Vec<Instruction>> {
+) -> Result<Vec<Instruction>, ClientError> {
     let payer = read_keypair_file(&config.payer_path)?;
     let url = Cluster::Custom(config.http_url.clone(), config.ws_url.clone());
-    let mint_account = &mut RpcClient::new(config.http_url.to_string()).get_account(&mint)?;
+    let mint_account = &mut build_rpc_client(config).get_account(&mint)?;
     // Client.
     let client = Client::new(url, Rc::new(payer));
     let (program, space) = if mint_account.owner == spl_token::id() {
@@ -177,7 +180,7 @@ pub fn close_token_account(
     close_account: &Pubkey,
     destination: &Pubkey,
     owner: &Keypair,
-) -> Result<Vec<Instruction>> {
+) -> Result<Vec<Instruction>, ClientError> {
     let payer = read_keypair_file(&config.payer_path)?;
     let url = Cluster::Custom(config.http_url.clone(), config.ws_url.clone());
     // Client.
@@ -203,7 +206,7 @@ pub fn spl_token_transfer_instr(
     to: &Pubkey,
     amount: u64,
     from_authority: &Keypair,
-) -> Result<Vec<Instruction>> {
+) -> Result<Vec<Instruction>, ClientError> {
     let payer = read_keypair_file(&config.payer_path)?;
     let url = Cluster::Custom(config.http_url.clone(), config.ws_url.clone());
     // Client.
@@ -231,7 +234,7 @@ pub fn spl_token_mint_to_instr(
     to: &Pubkey,
     amount: u64,
     mint_authority: &Keypair,
-) -> Result<Vec<Instruction>> {
+) -> Result<Vec<Instruction>, ClientError> {
     let payer = read_keypair_file(&config.payer_path)?;
     let url = Cluster::Custom(config.http_url.clone(), config.ws_url.clone());
     // Client.
@@ -256,7 +259,7 @@ pub fn spl_token_mint_to_instr(
     Ok(instructions)
 }
 
-pub fn wrap_sol_instr(config: &ClientConfig, amount: u64) -> Result<Vec<Instruction>> {
+pub fn wrap_sol_instr(config: &ClientConfig, amount: u64) -> Result<Vec<Instruction>, ClientError> {
     let payer = read_keypair_file(&config.payer_path)?;
     let wallet_key = payer.pubkey();
     let url = Cluster::Custom(config.http_url.clone(), config.ws_url.clone());
@@ -269,14 +272,11 @@ pub fn wrap_sol_instr(config: &ClientConfig, amount: u64) -> Result<Vec<Instruct
 
     let instructions = program
         .request()
-        .instruction(
-            spl_associated_token_account::instruction::create_associated_token_account_idempotent(
-                &program.payer(),
-                &wallet_key,
-                &wsol_mint,
-                &program.id(),
-            ),
-        )
+        .instruction(create_ata_idempotent_instr(
+            &wallet_key,
+            &wsol_mint,
+            &program.id(),
+        ))
         .instruction(system_instruction::transfer(
             &wallet_key,
             &wsol_ata_account,
@@ -289,3 +289,24 @@ pub fn wrap_sol_instr(config: &ClientConfig, amount: u64) -> Result<Vec<Instruct
         .instructions()?;
     Ok(instructions)
 }
+
+/// Create `owner`'s associated token account of `mint`, paid by `owner`. The instruction
+/// succeeds when the account already exists, so builders can include it unconditionally.
+pub fn create_ata_idempotent_instr(
+    owner: &Pubkey,
+    mint: &Pubkey,
+    token_program: &Pubkey,
+) -> Instruction {
+    spl_associated_token_account::instruction::create_associated_token_account_idempotent(
+        owner,
+        owner,
+        mint,
+        token_program,
+    )
+}
+
+/// Return the amount received after the mint's transfer fee for `epoch` is withheld.
+/// Mints without the transfer fee extension return `amount` unchanged.
+pub fn apply_transfer_fee(mint: &StateWithExtensions<Mint>, amount: u64, epoch: u64) -> u64 {
+    amount.saturating_sub(get_transfer_fee(mint, epoch, amount))
+}
@@ -1,8 +1,12 @@
-use anchor_lang::AccountDeserialize;
-use anyhow::Result;
+use anchor_lang::{ AccountDeserialize, Discriminator };
+use anyhow::{ anyhow, Result };
 use raydium_amm_v3::libraries::*;
 use raydium_amm_v3::states::*;
 
+use super::super::{ get_tick_array_key, ClientConfig, ClientError };
+use super::rpc::get_multiple_accounts_chunked;
+use super::token_instructions::apply_transfer_fee;
+
 use solana_client::rpc_client::RpcClient;
 use solana_sdk::program_pack::Pack;
 use solana_sdk::{ account::Account, pubkey::Pubkey };
@@ -23,14 +27,72 @@ use spl_token_2022::{
         ExtensionType,
         StateWithExtensions,
     },
-    state::Mint,
+    state::{ Account as TokenAccount, AccountState, Mint },
 };
-use std::collections::VecDeque;
-use std::ops::{ DerefMut, Mul, Neg };
+use std::collections::{ HashMap, VecDeque };
+use std::ops::{ Mul, Neg };
+use std::sync::OnceLock;
+
+/// Set `RAYDIUM_CLIENT_TRACE=1` to log the accounts and amounts used by the instruction builders.
+pub const TRACE_ENV: &str = "RAYDIUM_CLIENT_TRACE";
+
+/// Whether `TRACE_ENV` is set, read once.
+pub fn trace_enabled() -> bool {
+    static TRACE_ENABLED: OnceLock<bool> = OnceLock::new();
+    *TRACE_ENABLED.get_or_init(|| std::env::var(TRACE_ENV).is_ok_and(|value| value == "1"))
+}
 
-pub fn deserialize_anchor_account<T: AccountDeserialize>(account: &Account) -> Result<T> {
+/// Log through `log::info!` when `trace_enabled`, the arguments aren't evaluated otherwise.
+/// Only log public data such as pubkeys and amounts, never keypairs.
+macro_rules! builder_trace {
+    ($($arg:tt)*) => {
+        if $crate::instructions::utils::trace_enabled() {
+            log::info!(target: "raydium_client", $($arg)*);
+        }
+    };
+}
+pub(crate) use builder_trace;
+
+/// Returned by `deserialize_anchor_account` when the account data doesn't start with the
+/// discriminator of the expected account type.
+#[derive(Debug)]
+pub struct AccountDiscriminatorMismatch {
+    pub expected_type: &'static str,
+    pub expected: Vec<u8>,
+    pub actual: Vec<u8>,
+}
+
+impl std::fmt::Display for AccountDiscriminatorMismatch {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "account is not a {}, discriminator expected {:?}, got {:?}",
+            self.expected_type,
+            self.expected,
+            self.actual
+        )
+    }
+}
+
+impl std::error::Error for AccountDiscriminatorMismatch {}
+
+pub fn deserialize_anchor_account<T: AccountDeserialize + Discriminator>(
+    account: &Account
+) -> Result<T, ClientError> {
+    let actual = &account.data[..account.data.len().min(T::DISCRIMINATOR.len())];
+    if actual != T::DISCRIMINATOR {
+        return Err(
+            ClientError::DeserializeError(
+                (AccountDiscriminatorMismatch {
+                    expected_type: std::any::type_name::<T>(),
+                    expected: T::DISCRIMINATOR.to_vec(),
+                    actual: actual.to_vec(),
+                }).into()
+            )
+        );
+    }
     let mut data: &[u8] = &account.data;
-    T::try_deserialize(&mut data).map_err(Into::into)
+    T::try_deserialize(&mut data).map_err(|e| ClientError::DeserializeError(e.into()))
 }
 
 #[derive(Debug)]
@@ -65,6 +127,13 @@ pub fn amount_with_slippage(amount: u64, slippage: f64, round_up: bool) -> u64 {
     }
 }
 
+/// The minimum output to accept for a swap quoted at `quoted_out`, `slippage` being a fraction,
+/// e.g. 0.01 for 1%. Used as the swap's `other_amount_threshold` so that the swap fails instead
+/// of executing at a worse price.
+pub fn min_amount_out_with_slippage(quoted_out: u64, slippage: f64) -> u64 {
+    amount_with_slippage(quoted_out, slippage.clamp(0.0, 1.0), false)
+}
+
 pub fn get_pool_mints_inverse_fee(
     rpc_client: &RpcClient,
     token_mint_0: Pubkey,
@@ -224,14 +293,51 @@ pub fn get_account_extensions<'data, S: BaseState + Pack>(
     extensions
 }
 
+/// Check that tokens can be transferred out of `account`, which fails on-chain when the
+/// account is frozen by the mint's freeze authority or not initialized.
+pub fn assert_usable_token_account(account: &TokenAccount) -> Result<(), ClientError> {
+    match account.state {
+        AccountState::Initialized => Ok(()),
+        AccountState::Frozen =>
+            Err(anyhow!("token account of mint {} is frozen", account.mint).into()),
+        AccountState::Uninitialized => Err(anyhow!("token account is not initialized").into()),
+    }
+}
+
+/// Same as `assert_usable_token_account` for the raw data of a SPL Token or Token-2022 account.
+pub fn assert_usable_token_account_data(key: &Pubkey, data: &[u8]) -> Result<(), ClientError> {
+    let account = StateWithExtensions::<TokenAccount>
+        ::unpack(data)
+        .map_err(|e| anyhow!("{} is not a token account: {}", key, e))?;
+    assert_usable_token_account(&account.base).map_err(|e| anyhow!("{}: {}", key, e).into())
+}
+
+/// Order a mint pair as the program does, `token_mint_0` is the smaller address. The pool PDA
+/// is derived from the ordered pair.
+pub fn order_mints(a: Pubkey, b: Pubkey) -> (Pubkey, Pubkey) {
+    if a > b { (b, a) } else { (a, b) }
+}
+
 pub const Q_RATIO: f64 = 1.0001;
 
-pub fn tick_to_price(tick: i32) -> f64 {
-    Q_RATIO.powi(tick)
+/// Convert a tick to a human readable price, expressed as token1 per token0. See
+/// `tick_to_price_for_mints` to read the decimals from the mints.
+pub fn tick_to_price(tick: i32, decimals_0: u8, decimals_1: u8) -> f64 {
+    (Q_RATIO.powi(tick) * multipler(decimals_0)) / multipler(decimals_1)
 }
 
-pub fn price_to_tick(price: f64) -> i32 {
-    price.log(Q_RATIO) as i32
+/// Convert a human readable price (token1 per token0) to the nearest tick. See
+/// `price_to_tick_for_mints` to read the decimals from the mints.
+pub fn price_to_tick(price: f64, decimals_0: u8, decimals_1: u8) -> Result<i32, ClientError> {
+    if !price.is_finite() || price <= 0.0 {
+        return Err(anyhow!("price must be a positive finite number, got {}", price).into());
+    }
+    let price_with_decimals = (price * multipler(decimals_1)) / multipler(decimals_0);
+    let tick = price_with_decimals.log(Q_RATIO).round();
+    if tick < (tick_math::MIN_TICK as f64) || tick > (tick_math::MAX_TICK as f64) {
+        return Err(anyhow!("price {} is out of the representable tick range", price).into());
+    }
+    Ok(tick as i32)
 }
 
 pub fn tick_to_sqrt_price(tick: i32) -> f64 {
@@ -246,8 +352,192 @@ pub fn tick_with_spacing(tick: i32, tick_spacing: i32) -> i32 {
     compressed * tick_spacing
 }
 
+/// Check that `tick_lower < tick_upper`, and that both ticks are multiples of `tick_spacing`
+/// within `tick_math::MIN_TICK..=tick_math::MAX_TICK`, as the program requires of a position.
+pub fn validate_tick_range(
+    tick_lower: i32,
+    tick_upper: i32,
+    tick_spacing: u16
+) -> Result<(), ClientError> {
+    if tick_spacing == 0 {
+        return Err(anyhow!("tick_spacing must be positive").into());
+    }
+    if tick_lower >= tick_upper {
+        return Err(
+            anyhow!("tick_lower {} must be less than tick_upper {}", tick_lower, tick_upper).into()
+        );
+    }
+    for tick in [tick_lower, tick_upper] {
+        if !(tick_math::MIN_TICK..=tick_math::MAX_TICK).contains(&tick) {
+            return Err(
+                anyhow!(
+                    "tick {} is out of the range [{}, {}]",
+                    tick,
+                    tick_math::MIN_TICK,
+                    tick_math::MAX_TICK
+                ).into()
+            );
+        }
+        if tick % i32::from(tick_spacing) != 0 {
+            return Err(
+                anyhow!("tick {} is not a multiple of the tick spacing {}", tick, tick_spacing).into()
+            );
+        }
+    }
+    Ok(())
+}
+
+/// Convert the band of `center_price` ± `width_pct` (a fraction, e.g. 0.05 for ±5%) into a
+/// tick range aligned to `tick_spacing`. The lower tick is rounded down and the upper tick up,
+/// so the range always covers the band, and the range is at least one tick spacing wide.
+pub fn tick_range_for_price_band(
+    center_price: f64,
+    width_pct: f64,
+    tick_spacing: u16,
+    decimals_0: u8,
+    decimals_1: u8
+) -> Result<(i32, i32), ClientError> {
+    if !(width_pct > 0.0 && width_pct < 1.0) {
+        return Err(anyhow!("width_pct must be in (0, 1), got {}", width_pct).into());
+    }
+    if tick_spacing == 0 {
+        return Err(anyhow!("tick_spacing must be positive").into());
+    }
+    let tick_spacing = tick_spacing as i32;
+    let min_tick = tick_with_spacing(tick_math::MIN_TICK, tick_spacing) + tick_spacing;
+    let max_tick = tick_with_spacing(tick_math::MAX_TICK, tick_spacing);
+    let lower_price = center_price * (1.0 - width_pct);
+    let upper_price = center_price * (1.0 + width_pct);
+    let tick_lower = price_to_tick(lower_price, decimals_0, decimals_1)?;
+    let tick_upper = price_to_tick(upper_price, decimals_0, decimals_1)?;
+
+    let tick_lower = tick_with_spacing(tick_lower, tick_spacing).max(min_tick);
+    let mut aligned_upper = tick_with_spacing(tick_upper, tick_spacing);
+    if aligned_upper < tick_upper {
+        aligned_upper += tick_spacing; // round towards positive infinity
+    }
+    let tick_upper = aligned_upper.max(tick_lower + tick_spacing).min(max_tick);
+    if tick_lower >= tick_upper {
+        return Err(
+            anyhow!("price band around {} is out of the usable tick range", center_price).into()
+        );
+    }
+    Ok((tick_lower, tick_upper))
+}
+
+/// Impermanent loss of a position over `[tick_lower, tick_upper)` opened at `entry_price` and
+/// valued at `current_price`, as a fraction of the value of holding the deposited amounts
+/// instead (e.g. -0.05 for a 5% loss, never positive). Prices are token1 per token0 without the
+/// decimals adjustment, in the units of `tick_to_price(tick, 0, 0)`. Outside of the range the
+/// position is entirely in one token, so the loss keeps growing with the price move there.
+/// Returns NaN when a price isn't positive and finite or the range is empty.
+pub fn impermanent_loss(
+    entry_price: f64,
+    current_price: f64,
+    tick_lower: i32,
+    tick_upper: i32
+) -> f64 {
+    let valid_price = |price: f64| price.is_finite() && price > 0.0;
+    if !valid_price(entry_price) || !valid_price(current_price) || tick_lower >= tick_upper {
+        return f64::NAN;
+    }
+    let sqrt_price_lower = Q_RATIO.powi(tick_lower).sqrt();
+    let sqrt_price_upper = Q_RATIO.powi(tick_upper).sqrt();
+    // token amounts per unit of liquidity, the liquidity cancels out of the ratio
+    let amounts_at = |price: f64| -> (f64, f64) {
+        let sqrt_price = price.sqrt().clamp(sqrt_price_lower, sqrt_price_upper);
+        (1.0 / sqrt_price - 1.0 / sqrt_price_upper, sqrt_price - sqrt_price_lower)
+    };
+    let (entry_amount_0, entry_amount_1) = amounts_at(entry_price);
+    let (amount_0, amount_1) = amounts_at(current_price);
+    let held_value = entry_amount_0 * current_price + entry_amount_1;
+    let position_value = amount_0 * current_price + amount_1;
+    position_value / held_value - 1.0
+}
+
 pub fn multipler(decimals: u8) -> f64 {
-    (10_i32).checked_pow(decimals.try_into().unwrap()).unwrap() as f64
+    (10_f64).powi(decimals as i32)
+}
+
+/// The token program owning `mint`, either SPL Token or Token-2022.
+pub fn mint_token_program(mint: &Pubkey, mint_account: &Account) -> Result<Pubkey, ClientError> {
+    if mint_account.owner != spl_token::id() && mint_account.owner != spl_token_2022::id() {
+        return Err(
+            anyhow!("mint {} is owned by {}, not a token program", mint, mint_account.owner).into()
+        );
+    }
+    Ok(mint_account.owner)
+}
+
+/// The token programs of token0 and token1 of the pool, which may differ, e.g. token0 on SPL
+/// Token and token1 on Token-2022. Transfers of each side must use its own token program.
+pub fn resolve_token_programs(
+    rpc_client: &RpcClient,
+    pool_state: &PoolState
+) -> Result<(Pubkey, Pubkey), ClientError> {
+    let mint_keys = [pool_state.token_mint_0, pool_state.token_mint_1];
+    let mut token_programs = Vec::with_capacity(mint_keys.len());
+    for (mint, account) in mint_keys
+        .iter()
+        .zip(get_multiple_accounts_chunked(rpc_client, &mint_keys)?) {
+        let account = account.ok_or_else(|| anyhow!("mint {} not found", mint))?;
+        token_programs.push(mint_token_program(mint, &account)?);
+    }
+    Ok((token_programs[0], token_programs[1]))
+}
+
+/// Read the decimals of `mint`, either SPL Token or Token-2022, memoized in `cache`.
+pub fn get_mint_decimals(
+    rpc_client: &RpcClient,
+    mint: &Pubkey,
+    cache: &mut HashMap<Pubkey, u8>
+) -> Result<u8, ClientError> {
+    if let Some(decimals) = cache.get(mint) {
+        return Ok(*decimals);
+    }
+    let mint_account = rpc_client.get_account(mint)?;
+    let decimals = StateWithExtensions::<Mint>
+        ::unpack(&mint_account.data)
+        .map_err(|e| ClientError::DeserializeError(e.into()))?.base.decimals;
+    cache.insert(*mint, decimals);
+    Ok(decimals)
+}
+
+/// Convert a raw token amount of `mint` to human units, e.g. 1_500_000 of a 6 decimals mint is 1.5.
+pub fn to_ui_amount(
+    rpc_client: &RpcClient,
+    mint: &Pubkey,
+    amount: u64,
+    cache: &mut HashMap<Pubkey, u8>
+) -> Result<f64, ClientError> {
+    let decimals = get_mint_decimals(rpc_client, mint, cache)?;
+    Ok((amount as f64) / multipler(decimals))
+}
+
+/// Same as `tick_to_price`, with the decimals read from `mint_0` and `mint_1` through `cache`.
+pub fn tick_to_price_for_mints(
+    rpc_client: &RpcClient,
+    tick: i32,
+    mint_0: &Pubkey,
+    mint_1: &Pubkey,
+    cache: &mut HashMap<Pubkey, u8>
+) -> Result<f64, ClientError> {
+    let decimals_0 = get_mint_decimals(rpc_client, mint_0, cache)?;
+    let decimals_1 = get_mint_decimals(rpc_client, mint_1, cache)?;
+    Ok(tick_to_price(tick, decimals_0, decimals_1))
+}
+
+/// Same as `price_to_tick`, with the decimals read from `mint_0` and `mint_1` through `cache`.
+pub fn price_to_tick_for_mints(
+    rpc_client: &RpcClient,
+    price: f64,
+    mint_0: &Pubkey,
+    mint_1: &Pubkey,
+    cache: &mut HashMap<Pubkey, u8>
+) -> Result<i32, ClientError> {
+    let decimals_0 = get_mint_decimals(rpc_client, mint_0, cache)?;
+    let decimals_1 = get_mint_decimals(rpc_client, mint_1, cache)?;
+    price_to_tick(price, decimals_0, decimals_1)
 }
 
 pub fn price_to_x64(price: f64) -> u128 {
@@ -267,6 +557,781 @@ pub fn sqrt_price_x64_to_price(price: u128, decimals_0: u8, decimals_1: u8) -> f
     (from_x64_price(price).powi(2) * multipler(decimals_0)) / multipler(decimals_1)
 }
 
+/// Calculate the amounts of token0 and token1 required to deposit `liquidity` into the range
+/// `[tick_lower, tick_upper)` at the current sqrt price, rounded up as the program does. Fails
+/// on ticks out of range or amounts overflowing u64.
+pub fn liquidity_to_amounts(
+    sqrt_price_current: u128,
+    tick_lower: i32,
+    tick_upper: i32,
+    liquidity: u128
+) -> Result<(u64, u64), ClientError> {
+    let sqrt_price_lower = tick_math
+        ::get_sqrt_price_at_tick(tick_lower)
+        .map_err(|e| anyhow!("{}", e))?;
+    let sqrt_price_upper = tick_math
+        ::get_sqrt_price_at_tick(tick_upper)
+        .map_err(|e| anyhow!("{}", e))?;
+    let amount_0 = |sqrt_price_lower: u128| {
+        liquidity_math
+            ::get_delta_amount_0_unsigned(sqrt_price_lower, sqrt_price_upper, liquidity, true)
+            .map_err(|e| anyhow!("{}", e))
+    };
+    let amount_1 = |sqrt_price_upper: u128| {
+        liquidity_math
+            ::get_delta_amount_1_unsigned(sqrt_price_lower, sqrt_price_upper, liquidity, true)
+            .map_err(|e| anyhow!("{}", e))
+    };
+    if sqrt_price_current <= sqrt_price_lower {
+        // price below the range, only token0 is required
+        Ok((amount_0(sqrt_price_lower)?, 0))
+    } else if sqrt_price_current < sqrt_price_upper {
+        // price in the range, both tokens are required
+        Ok((amount_0(sqrt_price_current)?, amount_1(sqrt_price_current)?))
+    } else {
+        // price above the range, only token1 is required
+        Ok((0, amount_1(sqrt_price_upper)?))
+    }
+}
+
+/// Calculate the max liquidity that `amount_0` and `amount_1` can provide in the range
+/// `[tick_lower, tick_upper)` at the current sqrt price, limited by the scarcer side. Fails on
+/// ticks out of range.
+pub fn amounts_to_liquidity(
+    sqrt_price_current: u128,
+    tick_lower: i32,
+    tick_upper: i32,
+    amount_0: u64,
+    amount_1: u64
+) -> Result<u128, ClientError> {
+    Ok(
+        liquidity_math::get_liquidity_from_amounts(
+            sqrt_price_current,
+            tick_math::get_sqrt_price_at_tick(tick_lower).map_err(|e| anyhow!("{}", e))?,
+            tick_math::get_sqrt_price_at_tick(tick_upper).map_err(|e| anyhow!("{}", e))?,
+            amount_0,
+            amount_1
+        )
+    )
+}
+
+/// Calculate the liquidity for the given amounts together with the `amount_0_max` and
+/// `amount_1_max` of the open position or increase liquidity instruction, slippage applied.
+pub fn amounts_to_liquidity_with_slippage(
+    config: &ClientConfig,
+    sqrt_price_current: u128,
+    tick_lower: i32,
+    tick_upper: i32,
+    amount_0: u64,
+    amount_1: u64
+) -> Result<(u128, u64, u64), ClientError> {
+    let liquidity = amounts_to_liquidity(
+        sqrt_price_current,
+        tick_lower,
+        tick_upper,
+        amount_0,
+        amount_1
+    )?;
+    let (amount_0, amount_1) = liquidity_to_amounts(
+        sqrt_price_current,
+        tick_lower,
+        tick_upper,
+        liquidity
+    )?;
+    Ok((
+        liquidity,
+        amount_with_slippage(amount_0, config.slippage, true),
+        amount_with_slippage(amount_1, config.slippage, true),
+    ))
+}
+
+/// Resolve the sqrt price limit of a swap. `None` defaults to the min/max sqrt price in the swap
+/// direction, a given limit must be on the correct side of the current price.
+pub fn get_sqrt_price_limit_x64(
+    sqrt_price_limit_x64: Option<u128>,
+    zero_for_one: bool,
+    current_sqrt_price_x64: u128
+) -> Result<u128, ClientError> {
+    match sqrt_price_limit_x64 {
+        None if zero_for_one => Ok(tick_math::MIN_SQRT_PRICE_X64 + 1),
+        None => Ok(tick_math::MAX_SQRT_PRICE_X64 - 1),
+        Some(limit) if zero_for_one => {
+            if limit <= tick_math::MIN_SQRT_PRICE_X64 || limit >= current_sqrt_price_x64 {
+                return Err(
+                    anyhow!(
+                        "sqrt_price_limit_x64 {} must be within ({}, {}) for a zero_for_one swap",
+                        limit,
+                        tick_math::MIN_SQRT_PRICE_X64,
+                        current_sqrt_price_x64
+                    ).into()
+                );
+            }
+            Ok(limit)
+        }
+        Some(limit) => {
+            if limit >= tick_math::MAX_SQRT_PRICE_X64 || limit <= current_sqrt_price_x64 {
+                return Err(
+                    anyhow!(
+                        "sqrt_price_limit_x64 {} must be within ({}, {}) for a one_for_zero swap",
+                        limit,
+                        current_sqrt_price_x64,
+                        tick_math::MAX_SQRT_PRICE_X64
+                    ).into()
+                );
+            }
+            Ok(limit)
+        }
+    }
+}
+
+/// Fetch the pool and return the current price of token0 denominated in token1.
+pub fn get_pool_price(
+    rpc_client: &RpcClient,
+    pool_id: &Pubkey,
+    program_id: &Pubkey
+) -> Result<f64, ClientError> {
+    let pool_account = rpc_client.get_account(pool_id)?;
+    if pool_account.owner != *program_id {
+        return Err(anyhow!("pool {} is not owned by program {}", pool_id, program_id).into());
+    }
+    let pool_state = deserialize_anchor_account::<PoolState>(&pool_account)?;
+    Ok(
+        sqrt_price_x64_to_price(
+            pool_state.sqrt_price_x64,
+            pool_state.mint_decimals_0,
+            pool_state.mint_decimals_1
+        )
+    )
+}
+
+/// The time-weighted average price (token1 per token0) of the pool over about `seconds_ago`
+/// seconds, ending at the latest observation. The window starts at the observation closest to
+/// `latest - seconds_ago`, observations are only written by swaps at most every
+/// `OBSERVATION_UPDATE_DURATION_DEFAULT` seconds and the ring buffer only holds the last
+/// `OBSERVATION_NUM` of them, so the actual window may differ from the requested one.
+pub fn get_twap(
+    rpc_client: &RpcClient,
+    pool_id: &Pubkey,
+    program_id: &Pubkey,
+    seconds_ago: u32
+) -> Result<f64, ClientError> {
+    if seconds_ago == 0 {
+        return Err(anyhow!("seconds_ago must be positive").into());
+    }
+    let pool_account = rpc_client.get_account(pool_id)?;
+    if pool_account.owner != *program_id {
+        return Err(anyhow!("pool {} is not owned by program {}", pool_id, program_id).into());
+    }
+    let pool_state = deserialize_anchor_account::<PoolState>(&pool_account)?;
+    let observation_account = rpc_client.get_account(&pool_state.observation_key)?;
+    if observation_account.owner != *program_id {
+        return Err(
+            anyhow!(
+                "observation {} is not owned by program {}",
+                pool_state.observation_key,
+                program_id
+            ).into()
+        );
+    }
+    let observation_state = deserialize_anchor_account::<ObservationState>(&observation_account)?;
+    if !observation_state.initialized {
+        return Err(anyhow!("observation {} is not initialized", pool_state.observation_key).into());
+    }
+    let observations = observation_state.observations;
+    let latest = observations[observation_state.observation_index as usize];
+    let latest_timestamp = latest.block_timestamp;
+    let target_timestamp = latest_timestamp.saturating_sub(seconds_ago);
+
+    // pick the observation closest to the target, uninitialized slots have a zero timestamp
+    let start = observations
+        .iter()
+        .filter(|observation| {
+            let block_timestamp = observation.block_timestamp;
+            block_timestamp != 0 && block_timestamp < latest_timestamp
+        })
+        .min_by_key(|observation| {
+            let block_timestamp = observation.block_timestamp;
+            block_timestamp.abs_diff(target_timestamp)
+        })
+        .ok_or_else(|| anyhow!("pool {} has not enough observations for a TWAP", pool_id))?;
+    let start_timestamp = start.block_timestamp;
+    let start_tick_cumulative = start.tick_cumulative;
+    let latest_tick_cumulative = latest.tick_cumulative;
+
+    let elapsed = latest_timestamp - start_timestamp;
+    let average_tick =
+        (latest_tick_cumulative.wrapping_sub(start_tick_cumulative) as f64) / (elapsed as f64);
+    Ok(
+        (Q_RATIO.powf(average_tick) * multipler(pool_state.mint_decimals_0)) /
+            multipler(pool_state.mint_decimals_1)
+    )
+}
+
+/// Check that the spot prices of pools of the same mint pair agree, i.e. each is within
+/// `max_deviation_pct` of their median, as a fraction, e.g. `0.01` for 1%. A pool whose price
+/// strays from the others has likely been manipulated and shouldn't be used as an oracle.
+pub fn price_sanity_check(
+    rpc_client: &RpcClient,
+    pool_ids: &[Pubkey],
+    program_id: &Pubkey,
+    max_deviation_pct: f64
+) -> Result<bool, ClientError> {
+    if pool_ids.len() < 2 {
+        return Err(anyhow!("at least two pools are required to compare prices").into());
+    }
+    if !(max_deviation_pct >= 0.0) {
+        return Err(
+            anyhow!("max_deviation_pct must not be negative, got {}", max_deviation_pct).into()
+        );
+    }
+    let accounts = get_multiple_accounts_chunked(rpc_client, pool_ids)?;
+    let mut mint_pair = None;
+    let mut prices = Vec::with_capacity(pool_ids.len());
+    for (pool_id, account) in pool_ids.iter().zip(accounts) {
+        let account = account.ok_or_else(|| anyhow!("pool {} not found", pool_id))?;
+        if account.owner != *program_id {
+            return Err(anyhow!("pool {} is not owned by program {}", pool_id, program_id).into());
+        }
+        let pool_state = deserialize_anchor_account::<PoolState>(&account)?;
+        let pool_mints = (pool_state.token_mint_0, pool_state.token_mint_1);
+        match mint_pair {
+            None => {
+                mint_pair = Some(pool_mints);
+            }
+            Some(mints) if mints != pool_mints => {
+                return Err(
+                    anyhow!(
+                        "pool {} mints {} and {} differ from {} and {}",
+                        pool_id,
+                        pool_mints.0,
+                        pool_mints.1,
+                        mints.0,
+                        mints.1
+                    ).into()
+                );
+            }
+            _ => {}
+        }
+        prices.push(
+            sqrt_price_x64_to_price(
+                pool_state.sqrt_price_x64,
+                pool_state.mint_decimals_0,
+                pool_state.mint_decimals_1
+            )
+        );
+    }
+    prices.sort_by(f64::total_cmp);
+    let middle = prices.len() / 2;
+    let median = if prices.len() % 2 == 0 {
+        (prices[middle - 1] + prices[middle]) / 2.0
+    } else {
+        prices[middle]
+    };
+    if median == 0.0 {
+        return Ok(prices.iter().all(|price| *price == 0.0));
+    }
+    Ok(prices.iter().all(|price| (price / median - 1.0).abs() <= max_deviation_pct))
+}
+
+/// The funding state of an initialized reward of a pool, see `reward_runway`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct RewardRunway {
+    pub reward_index: usize,
+    pub token_mint: Pubkey,
+    pub token_vault: Pubkey,
+    pub vault_balance: u64,
+    /// Q64.64 reward tokens emitted per second
+    pub emissions_per_second_x64: u128,
+    /// The scheduled end of the emissions
+    pub end_time: u64,
+    /// When the vault balance left after the unclaimed rewards runs out at the current emission
+    /// rate, always before `end_time`. `None` if nothing is emitted or the vault covers the
+    /// emissions up to `end_time`
+    pub run_out_time: Option<u64>,
+}
+
+/// Report the vault balance and the estimated time the vault runs dry for each initialized
+/// reward of the pool. Rewards emitted but not claimed yet are still in the vault, they are
+/// deducted from the balance before estimating the runway. Only underfunded rewards get a
+/// `run_out_time`, rewards whose vault covers the schedule up to `end_time` report `None`.
+pub fn reward_runway(
+    rpc_client: &RpcClient,
+    pool_id: &Pubkey,
+    program_id: &Pubkey
+) -> Result<Vec<RewardRunway>, ClientError> {
+    let pool_account = rpc_client.get_account(pool_id)?;
+    if pool_account.owner != *program_id {
+        return Err(anyhow!("pool {} is not owned by program {}", pool_id, program_id).into());
+    }
+    let pool_state = deserialize_anchor_account::<PoolState>(&pool_account)?;
+    let reward_infos = pool_state.reward_infos;
+    let rewards = reward_infos
+        .iter()
+        .enumerate()
+        .filter(|(_, reward_info)| reward_info.initialized())
+        .collect::<Vec<_>>();
+    let vault_keys = rewards
+        .iter()
+        .map(|(_, reward_info)| reward_info.token_vault)
+        .collect::<Vec<Pubkey>>();
+    let vault_accounts = rpc_client.get_multiple_accounts(&vault_keys)?;
+
+    let mut runways = Vec::with_capacity(rewards.len());
+    for ((reward_index, reward_info), vault_account) in rewards.into_iter().zip(vault_accounts) {
+        let token_vault = reward_info.token_vault;
+        let vault_account = vault_account.ok_or_else(||
+            anyhow!("reward vault {} not found", token_vault)
+        )?;
+        let vault_balance = StateWithExtensions::<TokenAccount>
+            ::unpack(&vault_account.data)
+            .map_err(|e| ClientError::DeserializeError(e.into()))?.base.amount;
+        let emissions_per_second_x64 = reward_info.emissions_per_second_x64;
+        let unclaimed = reward_info.reward_total_emissioned.saturating_sub(
+            reward_info.reward_claimed
+        );
+        let run_out_time = if emissions_per_second_x64 == 0 {
+            None
+        } else {
+            let remaining = vault_balance.saturating_sub(unclaimed);
+            let seconds = U256::from(remaining)
+                .mul_div_floor(U256::from(fixed_point_64::Q64), U256::from(emissions_per_second_x64))
+                .unwrap();
+            let seconds = if seconds > U256::from(u64::MAX) { u64::MAX } else { seconds.as_u64() };
+            let run_out_time = reward_info.last_update_time.saturating_add(seconds);
+            // emissions stop at end_time, a vault lasting until then is fully funded
+            if run_out_time < reward_info.end_time { Some(run_out_time) } else { None }
+        };
+        runways.push(RewardRunway {
+            reward_index,
+            token_mint: reward_info.token_mint,
+            token_vault,
+            vault_balance,
+            emissions_per_second_x64,
+            end_time: reward_info.end_time,
+            run_out_time,
+        });
+    }
+    Ok(runways)
+}
+
+/// List the start indices of all initialized tick arrays of the pool in ascending order,
+/// from both the pool's default bitmap and the tick array bitmap extension.
+pub fn list_initialized_tick_arrays(
+    pool: &PoolState,
+    bitmap_ext: &TickArrayBitmapExtension
+) -> Vec<i32> {
+    let tick_spacing = pool.tick_spacing;
+    let tick_count = TickArrayState::tick_count(tick_spacing);
+    let bitmap_boundary = tick_array_bit_map::max_tick_in_tickarray_bitmap(tick_spacing);
+    let mut start_indices = Vec::new();
+    let mut start_index = TickArrayState::get_array_start_index(tick_math::MIN_TICK, tick_spacing);
+    // tick arrays in [-bitmap_boundary, bitmap_boundary) are tracked by the default bitmap
+    while start_index < -bitmap_boundary {
+        if
+            bitmap_ext
+                .check_tick_array_is_initialized(start_index, tick_spacing)
+                .map_or(false, |(initialized, _)| initialized)
+        {
+            start_indices.push(start_index);
+        }
+        start_index += tick_count;
+    }
+    let tick_array_bitmap = pool.tick_array_bitmap;
+    for (word_index, word) in tick_array_bitmap.iter().enumerate() {
+        for bit_index in 0..64 {
+            if word & (1u64 << bit_index) != 0 {
+                let offset = (word_index * 64 + bit_index) as i32;
+                start_indices.push(
+                    (offset - tick_array_bit_map::TICK_ARRAY_BITMAP_SIZE) * tick_count
+                );
+            }
+        }
+    }
+    start_index = bitmap_boundary;
+    while start_index <= tick_math::MAX_TICK {
+        if
+            bitmap_ext
+                .check_tick_array_is_initialized(start_index, tick_spacing)
+                .map_or(false, |(initialized, _)| initialized)
+        {
+            start_indices.push(start_index);
+        }
+        start_index += tick_count;
+    }
+    start_indices
+}
+
+/// The nearest initialized tick above `tick` when `search_up`, or at or below `tick` otherwise,
+/// the same way a swap crosses ticks. The bitmaps locate the tick arrays to search, which must
+/// be in `tick_arrays`, e.g. as returned by `load_tick_arrays_both_directions`. Returns `None`
+/// when the pool has no initialized tick in that direction.
+pub fn nearest_initialized_tick(
+    pool: &PoolState,
+    bitmap_ext: &TickArrayBitmapExtension,
+    tick_arrays: &VecDeque<TickArrayState>,
+    tick: i32,
+    search_up: bool
+) -> Result<Option<i32>, ClientError> {
+    let tick_spacing = pool.tick_spacing;
+    let zero_for_one = !search_up;
+    let loaded_tick_array = |start_index: i32| {
+        tick_arrays
+            .iter()
+            .find(|tick_array| tick_array.start_tick_index == start_index)
+            .copied()
+            .ok_or_else(|| anyhow!("tick array starting at {} is not loaded", start_index))
+    };
+    let start_index = TickArrayState::get_array_start_index(tick, tick_spacing);
+    let tick_array_status = if pool.is_overflow_default_tickarray_bitmap(vec![tick]) {
+        bitmap_ext.check_tick_array_is_initialized(start_index, tick_spacing)
+    } else {
+        tick_array_bit_map::check_current_tick_array_is_initialized(
+            U1024(pool.tick_array_bitmap),
+            tick,
+            tick_spacing
+        )
+    };
+    let (initialized, _) = tick_array_status.map_err(|e| anyhow!("{}", e))?;
+    if initialized {
+        let mut tick_array = loaded_tick_array(start_index)?;
+        if
+            let Some(tick_state) = tick_array
+                .next_initialized_tick(tick, tick_spacing, zero_for_one)
+                .map_err(|e| anyhow!("{}", e))?
+        {
+            return Ok(Some(tick_state.tick));
+        }
+    }
+    let next_start_index = pool
+        .next_initialized_tick_array_start_index(&Some(*bitmap_ext), start_index, zero_for_one)
+        .map_err(|e| anyhow!("{}", e))?;
+    let Some(next_start_index) = next_start_index else {
+        return Ok(None);
+    };
+    // an initialized tick array holds at least one initialized tick
+    let mut tick_array = loaded_tick_array(next_start_index)?;
+    let tick_state = tick_array.first_initialized_tick(zero_for_one).map_err(|e| anyhow!("{}", e))?;
+    Ok(Some(tick_state.tick))
+}
+
+/// Fetch the global operation state, whose PDA is derived from `OPERATION_SEED`.
+pub fn get_operation_state(
+    rpc_client: &RpcClient,
+    program_id: &Pubkey
+) -> Result<OperationState, ClientError> {
+    let (operation_state_key, _bump) = Pubkey::find_program_address(
+        &[OPERATION_SEED.as_bytes()],
+        program_id
+    );
+    let operation_account = rpc_client.get_account(&operation_state_key)?;
+    if operation_account.owner != *program_id {
+        return Err(
+            anyhow!(
+                "operation state {} is not owned by program {}",
+                operation_state_key,
+                program_id
+            ).into()
+        );
+    }
+    deserialize_anchor_account::<OperationState>(&operation_account)
+}
+
+pub fn is_operation_owner(state: &OperationState, key: &Pubkey) -> bool {
+    state.validate_operation_owner(*key)
+}
+
+/// Fetch the amm config of the fee tier `index`.
+pub fn get_amm_config(
+    rpc_client: &RpcClient,
+    program_id: &Pubkey,
+    index: u16
+) -> Result<AmmConfig, ClientError> {
+    let (amm_config_key, _bump) = Pubkey::find_program_address(
+        &[AMM_CONFIG_SEED.as_bytes(), &index.to_be_bytes()],
+        program_id
+    );
+    let amm_config_account = rpc_client.get_account(&amm_config_key)?;
+    if amm_config_account.owner != *program_id {
+        return Err(
+            anyhow!("amm config {} is not owned by program {}", amm_config_key, program_id).into()
+        );
+    }
+    deserialize_anchor_account::<AmmConfig>(&amm_config_account)
+}
+
+/// The tick spacing and fee rates of a fee tier. Fee rates are denominated in hundredths of
+/// a bip, see `FEE_RATE_DENOMINATOR_VALUE`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct FeeTier {
+    pub tick_spacing: u16,
+    pub trade_fee_rate: u32,
+    pub protocol_fee_rate: u32,
+    pub fund_fee_rate: u32,
+}
+
+impl FeeTier {
+    /// The trade fee in percent, e.g. 0.25 for a rate of 2500.
+    pub fn trade_fee_pct(&self) -> f64 {
+        ((self.trade_fee_rate as f64) * 100.0) / (FEE_RATE_DENOMINATOR_VALUE as f64)
+    }
+}
+
+impl From<&AmmConfig> for FeeTier {
+    fn from(amm_config: &AmmConfig) -> Self {
+        FeeTier {
+            tick_spacing: amm_config.tick_spacing,
+            trade_fee_rate: amm_config.trade_fee_rate,
+            protocol_fee_rate: amm_config.protocol_fee_rate,
+            fund_fee_rate: amm_config.fund_fee_rate,
+        }
+    }
+}
+
+pub fn amm_config_tick_spacing(amm_config: &AmmConfig) -> u16 {
+    amm_config.tick_spacing
+}
+
+pub fn amm_config_trade_fee_rate(amm_config: &AmmConfig) -> u32 {
+    amm_config.trade_fee_rate
+}
+
+pub fn amm_config_protocol_fee_rate(amm_config: &AmmConfig) -> u32 {
+    amm_config.protocol_fee_rate
+}
+
+pub fn amm_config_fund_fee_rate(amm_config: &AmmConfig) -> u32 {
+    amm_config.fund_fee_rate
+}
+
+/// Where the pool's current tick is relative to a position's range, with the distance in ticks
+/// to the nearest boundary of the range.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum PositionRangeStatus {
+    /// The position is active and earns fees.
+    InRange {
+        ticks_to_boundary: u32,
+    },
+    /// The current tick is below `tick_lower_index`, the position holds only token0.
+    BelowRange {
+        ticks_to_lower: u32,
+    },
+    /// The current tick is at or above `tick_upper_index`, the position holds only token1.
+    AboveRange {
+        ticks_to_upper: u32,
+    },
+}
+
+pub fn position_status(pool: &PoolState, position: &PersonalPositionState) -> PositionRangeStatus {
+    let tick_current = pool.tick_current;
+    if tick_current < position.tick_lower_index {
+        PositionRangeStatus::BelowRange {
+            ticks_to_lower: position.tick_lower_index.abs_diff(tick_current),
+        }
+    } else if tick_current < position.tick_upper_index {
+        PositionRangeStatus::InRange {
+            ticks_to_boundary: u32::min(
+                tick_current.abs_diff(position.tick_lower_index),
+                position.tick_upper_index.abs_diff(tick_current)
+            ),
+        }
+    } else {
+        PositionRangeStatus::AboveRange {
+            ticks_to_upper: tick_current.abs_diff(position.tick_upper_index),
+        }
+    }
+}
+
+/// Operations enabled by the `status` bitfield of a pool, where a set bit disables the
+/// operation of its `PoolStatusBitIndex`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct PoolStatusFlags {
+    pub swap_enabled: bool,
+    /// Opening positions and increasing liquidity.
+    pub deposit_enabled: bool,
+    /// Decreasing liquidity, fees and rewards are collected as long as their own bits allow.
+    pub withdraw_enabled: bool,
+    pub collect_fee_enabled: bool,
+    pub collect_reward_enabled: bool,
+}
+
+impl PoolStatusFlags {
+    pub const ALL_ENABLED: Self = Self {
+        swap_enabled: true,
+        deposit_enabled: true,
+        withdraw_enabled: true,
+        collect_fee_enabled: true,
+        collect_reward_enabled: true,
+    };
+
+    pub fn from_pool(pool: &PoolState) -> Self {
+        Self::from_status(pool.status)
+    }
+
+    pub fn from_status(status: u8) -> Self {
+        let enabled = |bit: PoolStatusBitIndex| status & (1u8 << (bit as u8)) == 0;
+        Self {
+            swap_enabled: enabled(PoolStatusBitIndex::Swap),
+            deposit_enabled: enabled(PoolStatusBitIndex::OpenPositionOrIncreaseLiquidity),
+            withdraw_enabled: enabled(PoolStatusBitIndex::DecreaseLiquidity),
+            collect_fee_enabled: enabled(PoolStatusBitIndex::CollectFee),
+            collect_reward_enabled: enabled(PoolStatusBitIndex::CollectReward),
+        }
+    }
+
+    /// The status byte to pass to `update_pool_status`, e.g. to pause swaps only:
+    /// `PoolStatusFlags { swap_enabled: false, ..PoolStatusFlags::ALL_ENABLED }.to_status()`.
+    pub fn to_status(&self) -> u8 {
+        let disabled = |enabled: bool, bit: PoolStatusBitIndex| {
+            if enabled { 0 } else { 1u8 << (bit as u8) }
+        };
+        disabled(self.swap_enabled, PoolStatusBitIndex::Swap) |
+            disabled(self.deposit_enabled, PoolStatusBitIndex::OpenPositionOrIncreaseLiquidity) |
+            disabled(self.withdraw_enabled, PoolStatusBitIndex::DecreaseLiquidity) |
+            disabled(self.collect_fee_enabled, PoolStatusBitIndex::CollectFee) |
+            disabled(self.collect_reward_enabled, PoolStatusBitIndex::CollectReward)
+    }
+
+    pub fn ensure_swap_enabled(&self) -> Result<(), ClientError> {
+        if !self.swap_enabled {
+            return Err(ClientError::PoolPaused("swapping"));
+        }
+        Ok(())
+    }
+
+    pub fn ensure_deposit_enabled(&self) -> Result<(), ClientError> {
+        if !self.deposit_enabled {
+            return Err(ClientError::PoolPaused("opening positions and increasing liquidity"));
+        }
+        Ok(())
+    }
+
+    pub fn ensure_withdraw_enabled(&self) -> Result<(), ClientError> {
+        if !self.withdraw_enabled {
+            return Err(ClientError::PoolPaused("decreasing liquidity"));
+        }
+        Ok(())
+    }
+}
+
+impl std::fmt::Display for PoolStatusFlags {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let state = |enabled: bool| if enabled { "enabled" } else { "disabled" };
+        write!(
+            f,
+            "swap {}, deposit {}, withdraw {}, collect fee {}, collect reward {}",
+            state(self.swap_enabled),
+            state(self.deposit_enabled),
+            state(self.withdraw_enabled),
+            state(self.collect_fee_enabled),
+            state(self.collect_reward_enabled)
+        )
+    }
+}
+
+/// Calculate the fees of token0 and token1 owed to the position, including the fees
+/// accrued since the last time the position was updated on-chain.
+pub fn compute_fees_owed(
+    pool: &PoolState,
+    position: &PersonalPositionState,
+    tick_lower: &TickState,
+    tick_upper: &TickState
+) -> (u64, u64) {
+    let fee_growth_global_0_x64 = pool.fee_growth_global_0_x64;
+    let fee_growth_global_1_x64 = pool.fee_growth_global_1_x64;
+    // calculate fee growth below
+    let (fee_growth_below_0_x64, fee_growth_below_1_x64) = if pool.tick_current >= tick_lower.tick {
+        (tick_lower.fee_growth_outside_0_x64, tick_lower.fee_growth_outside_1_x64)
+    } else {
+        (
+            fee_growth_global_0_x64.wrapping_sub(tick_lower.fee_growth_outside_0_x64),
+            fee_growth_global_1_x64.wrapping_sub(tick_lower.fee_growth_outside_1_x64),
+        )
+    };
+    // calculate fee growth above
+    let (fee_growth_above_0_x64, fee_growth_above_1_x64) = if pool.tick_current < tick_upper.tick {
+        (tick_upper.fee_growth_outside_0_x64, tick_upper.fee_growth_outside_1_x64)
+    } else {
+        (
+            fee_growth_global_0_x64.wrapping_sub(tick_upper.fee_growth_outside_0_x64),
+            fee_growth_global_1_x64.wrapping_sub(tick_upper.fee_growth_outside_1_x64),
+        )
+    };
+    let fee_growth_inside_0_x64 = fee_growth_global_0_x64
+        .wrapping_sub(fee_growth_below_0_x64)
+        .wrapping_sub(fee_growth_above_0_x64);
+    let fee_growth_inside_1_x64 = fee_growth_global_1_x64
+        .wrapping_sub(fee_growth_below_1_x64)
+        .wrapping_sub(fee_growth_above_1_x64);
+
+    let fee_delta = |fee_growth_inside_latest_x64: u128, fee_growth_inside_last_x64: u128| {
+        U128::from(fee_growth_inside_latest_x64.wrapping_sub(fee_growth_inside_last_x64))
+            .mul_div_floor(U128::from(position.liquidity), U128::from(fixed_point_64::Q64))
+            .unwrap()
+            .to_underflow_u64()
+    };
+    (
+        position.token_fees_owed_0.saturating_add(
+            fee_delta(fee_growth_inside_0_x64, position.fee_growth_inside_0_last_x64)
+        ),
+        position.token_fees_owed_1.saturating_add(
+            fee_delta(fee_growth_inside_1_x64, position.fee_growth_inside_1_last_x64)
+        ),
+    )
+}
+
+/// The fee growth inside the position's range, per unit of liquidity as Q64.64, recorded the last
+/// time the position was updated on-chain, as (token_0, token_1).
+pub fn position_fee_checkpoints(position: &PersonalPositionState) -> (u128, u128) {
+    (position.fee_growth_inside_0_last_x64, position.fee_growth_inside_1_last_x64)
+}
+
+/// Calculate the rewards owed to the position for each reward slot of the pool, including the
+/// rewards accrued since the last time the position was updated on-chain. The reward growths of
+/// the pool are first advanced to `current_timestamp`, e.g. the unix timestamp of the `Clock`
+/// sysvar, as `update_reward_infos` does on-chain. Uninitialized reward slots are 0.
+pub fn compute_rewards_owed(
+    pool: &PoolState,
+    position: &PersonalPositionState,
+    tick_lower: &TickState,
+    tick_upper: &TickState,
+    current_timestamp: u64
+) -> Result<[u64; REWARD_NUM], ClientError> {
+    let reward_infos = pool
+        .clone()
+        .update_reward_infos(current_timestamp)
+        .map_err(|e| anyhow!("{}", e))?;
+    let mut rewards_owed = [0u64; REWARD_NUM];
+    for (i, reward_info) in reward_infos.iter().enumerate() {
+        if !reward_info.initialized() {
+            continue;
+        }
+        let reward_growth_global_x64 = reward_info.reward_growth_global_x64;
+        let reward_growth_below_x64 = if pool.tick_current >= tick_lower.tick {
+            tick_lower.reward_growths_outside_x64[i]
+        } else {
+            reward_growth_global_x64.wrapping_sub(tick_lower.reward_growths_outside_x64[i])
+        };
+        let reward_growth_above_x64 = if pool.tick_current < tick_upper.tick {
+            tick_upper.reward_growths_outside_x64[i]
+        } else {
+            reward_growth_global_x64.wrapping_sub(tick_upper.reward_growths_outside_x64[i])
+        };
+        let reward_growth_inside_x64 = reward_growth_global_x64
+            .wrapping_sub(reward_growth_below_x64)
+            .wrapping_sub(reward_growth_above_x64);
+
+        let position_reward_info = position.reward_infos[i];
+        let reward_delta = U256::from(
+            reward_growth_inside_x64.wrapping_sub(position_reward_info.growth_inside_last_x64)
+        )
+            .mul_div_floor(U256::from(position.liquidity), U256::from(fixed_point_64::Q64))
+            .unwrap()
+            .to_underflow_u64();
+        rewards_owed[i] = position_reward_info.reward_amount_owed.saturating_add(reward_delta);
+    }
+    Ok(rewards_owed)
+}
+
 // the top level state of the swap, the results of which are recorded in storage at the end
 #[derive(Debug)]
 pub struct SwapState {
@@ -281,6 +1346,21 @@ pub struct SwapState {
     // the current liquidity in range
     pub liquidity: u128,
 }
+/// The expected outcome of a swap, computed off-chain
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct SwapQuote {
+    // the amount of the output asset received
+    pub amount_out: u64,
+    // the amount of the input asset consumed, including the trade fee
+    pub amount_in_consumed: u64,
+    // the trade fee paid in the input asset
+    pub fee_amount: u64,
+    // sqrt(price) after the swap
+    pub sqrt_price_x64: u128,
+    // the tick associated with the price after the swap
+    pub tick: i32,
+}
+
 #[derive(Default)]
 struct StepComputations {
     // the price at the beginning of the step
@@ -299,6 +1379,57 @@ struct StepComputations {
     fee_amount: u64,
 }
 
+/// The first initialized tick array in the swap direction as `(is_pool_current_tick_array,
+/// start_index)`, see `PoolState::get_first_initialized_tick_array`. Pools without a bitmap
+/// extension fail instead of panicking when the current tick is past the default bitmap.
+pub(crate) fn first_initialized_tick_array(
+    pool_state: &PoolState,
+    tickarray_bitmap_extension: &Option<TickArrayBitmapExtension>,
+    zero_for_one: bool
+) -> Result<(bool, i32)> {
+    let tick_current = pool_state.tick_current;
+    if
+        tickarray_bitmap_extension.is_none() &&
+        pool_state.is_overflow_default_tickarray_bitmap(vec![tick_current])
+    {
+        return Err(
+            anyhow!(
+                "tick {} is past the default bitmap, which requires the missing bitmap extension",
+                tick_current
+            )
+        );
+    }
+    pool_state
+        .get_first_initialized_tick_array(tickarray_bitmap_extension, zero_for_one)
+        .map_err(|e| anyhow!("{}", e))
+}
+
+/// The pool mints and the current epoch, for swap quotes to account for the Token-2022 transfer
+/// fees charged on the transfers in and out of the pool.
+pub struct SwapTransferFees<'a> {
+    pub mint_0: &'a StateWithExtensions<'a, Mint>,
+    pub mint_1: &'a StateWithExtensions<'a, Mint>,
+    pub epoch: u64,
+}
+
+impl SwapTransferFees<'_> {
+    /// The input and output mints of the swap direction.
+    fn input_output_mints(
+        &self,
+        zero_for_one: bool
+    ) -> (&StateWithExtensions<'_, Mint>, &StateWithExtensions<'_, Mint>) {
+        if zero_for_one { (self.mint_0, self.mint_1) } else { (self.mint_1, self.mint_0) }
+    }
+
+    /// The amount the user sends for the pool to receive `amount`.
+    fn gross_input(&self, zero_for_one: bool, amount: u64) -> Result<u64> {
+        let (input_mint, _) = self.input_output_mints(zero_for_one);
+        amount
+            .checked_add(get_transfer_inverse_fee(input_mint, self.epoch, amount))
+            .ok_or_else(|| anyhow!("input amount overflow"))
+    }
+}
+
 pub fn get_out_put_amount_and_remaining_accounts(
     input_amount: u64,
     sqrt_price_limit_x64: Option<u128>,
@@ -306,30 +1437,290 @@ pub fn get_out_put_amount_and_remaining_accounts(
     is_base_input: bool,
     pool_config: &AmmConfig,
     pool_state: &PoolState,
-    tickarray_bitmap_extension: &TickArrayBitmapExtension,
-    tick_arrays: &mut VecDeque<TickArrayState>
-) -> Result<(u64, VecDeque<i32>), &'static str> {
-    let (is_pool_current_tick_array, current_valid_tick_array_start_index) = pool_state
-        .get_first_initialized_tick_array(&Some(*tickarray_bitmap_extension), zero_for_one)
-        .unwrap();
+    tickarray_bitmap_extension: &Option<TickArrayBitmapExtension>,
+    tick_arrays: &mut VecDeque<TickArrayState>,
+    transfer_fees: Option<&SwapTransferFees>
+) -> Result<(u64, VecDeque<i32>), ClientError> {
+    let (is_pool_current_tick_array, current_valid_tick_array_start_index) =
+        first_initialized_tick_array(pool_state, tickarray_bitmap_extension, zero_for_one)?;
+    // the pool receives the input net of its transfer fee, and must send the output gross of it
+    let amount_specified = match transfer_fees {
+        Some(fees) if is_base_input => {
+            apply_transfer_fee(fees.input_output_mints(zero_for_one).0, input_amount, fees.epoch)
+        }
+        Some(fees) => {
+            let output_mint = fees.input_output_mints(zero_for_one).1;
+            input_amount
+                .checked_add(get_transfer_inverse_fee(output_mint, fees.epoch, input_amount))
+                .ok_or_else(|| anyhow!("output amount overflow"))?
+        }
+        None => input_amount,
+    };
 
-    let (amount_calculated, tick_array_start_index_vec) = swap_compute(
+    let (quote, tick_array_start_index_vec) = swap_compute(
         zero_for_one,
         is_base_input,
         is_pool_current_tick_array,
         pool_config.trade_fee_rate,
-        input_amount,
+        amount_specified,
         current_valid_tick_array_start_index,
         sqrt_price_limit_x64.unwrap_or(0),
         pool_state,
         tickarray_bitmap_extension,
         tick_arrays
     )?;
-    println!("tick_array_start_index:{:?}", tick_array_start_index_vec);
+    builder_trace!(
+        "get_out_put_amount_and_remaining_accounts: tick arrays {:?}",
+        tick_array_start_index_vec
+    );
 
+    let amount_calculated = match transfer_fees {
+        Some(fees) if is_base_input => {
+            apply_transfer_fee(fees.input_output_mints(zero_for_one).1, quote.amount_out, fees.epoch)
+        }
+        Some(fees) =>
+            fees.gross_input(zero_for_one, quote.amount_in_consumed)?,
+        None if is_base_input => quote.amount_out,
+        None => quote.amount_in_consumed,
+    };
     Ok((amount_calculated, tick_array_start_index_vec))
 }
 
+/// Simulate an exact input swap against the given pool snapshot without any RPC round-trip.
+/// `tick_arrays` must start with the first initialized tick array in the swap direction,
+/// e.g. as returned by `load_cur_and_next_five_tick_array`. With `transfer_fees`, the
+/// Token-2022 transfer fees of the pool mints are accounted for: `amount_in_consumed` is what
+/// the user sends and `amount_out` what the user receives. Fails with
+/// `ClientError::InsufficientLiquidity` if `tick_arrays`, or the pool liquidity, run out before
+/// `amount_in` is consumed.
+pub fn compute_swap_quote(
+    amm_config: &AmmConfig,
+    pool_state: &PoolState,
+    tick_arrays: &mut VecDeque<TickArrayState>,
+    tickarray_bitmap_extension: &Option<TickArrayBitmapExtension>,
+    amount_in: u64,
+    zero_for_one: bool,
+    sqrt_price_limit_x64: Option<u128>,
+    transfer_fees: Option<&SwapTransferFees>
+) -> Result<SwapQuote, ClientError> {
+    let (is_pool_current_tick_array, current_valid_tick_array_start_index) =
+        first_initialized_tick_array(pool_state, tickarray_bitmap_extension, zero_for_one)?;
+    if tick_arrays.is_empty() {
+        return Err(anyhow!("at least one tick array is required to quote").into());
+    }
+    let amount_specified = match transfer_fees {
+        Some(fees) =>
+            apply_transfer_fee(fees.input_output_mints(zero_for_one).0, amount_in, fees.epoch),
+        None => amount_in,
+    };
+    let (mut quote, _) = swap_compute(
+        zero_for_one,
+        true,
+        is_pool_current_tick_array,
+        amm_config.trade_fee_rate,
+        amount_specified,
+        current_valid_tick_array_start_index,
+        sqrt_price_limit_x64.unwrap_or(0),
+        pool_state,
+        tickarray_bitmap_extension,
+        tick_arrays
+    )?;
+    if let Some(fees) = transfer_fees {
+        quote.amount_in_consumed = fees.gross_input(zero_for_one, quote.amount_in_consumed)?;
+        quote.amount_out = apply_transfer_fee(
+            fees.input_output_mints(zero_for_one).1,
+            quote.amount_out,
+            fees.epoch
+        );
+    }
+    Ok(quote)
+}
+
+/// The keys of the tick arrays an exact input swap of `amount_in` crosses, in swap order,
+/// found by simulating the swap against `tick_arrays`, which must start with the first
+/// initialized tick array in the swap direction and cover the whole swap, e.g. as returned by
+/// `load_cur_and_next_tick_arrays`. Pass the result as the `tick_array_keys` of
+/// `build_swap_instr` instead of a fixed window.
+pub fn tick_arrays_for_swap(
+    program_id: &Pubkey,
+    pool_id: &Pubkey,
+    amm_config: &AmmConfig,
+    pool_state: &PoolState,
+    tickarray_bitmap_extension: &Option<TickArrayBitmapExtension>,
+    tick_arrays: &mut VecDeque<TickArrayState>,
+    amount_in: u64,
+    zero_for_one: bool
+) -> Result<Vec<Pubkey>, ClientError> {
+    let (is_pool_current_tick_array, current_valid_tick_array_start_index) =
+        first_initialized_tick_array(pool_state, tickarray_bitmap_extension, zero_for_one)?;
+    if tick_arrays.is_empty() {
+        return Err(anyhow!("at least one tick array is required to simulate the swap").into());
+    }
+    let (_, tick_array_start_index_vec) = swap_compute(
+        zero_for_one,
+        true,
+        is_pool_current_tick_array,
+        amm_config.trade_fee_rate,
+        amount_in,
+        current_valid_tick_array_start_index,
+        0,
+        pool_state,
+        tickarray_bitmap_extension,
+        tick_arrays
+    )?;
+    Ok(
+        tick_array_start_index_vec
+            .into_iter()
+            .map(|start_index| get_tick_array_key(program_id, pool_id, start_index))
+            .collect()
+    )
+}
+
+/// The keys of `tick_arrays` in the order the swap program expects them in the remaining
+/// accounts: starting at the first initialized tick array in the swap direction, see
+/// `PoolState::get_first_initialized_tick_array`, then descending start index for a zero for one
+/// swap, ascending otherwise. Tick arrays behind the first one can't be crossed by the swap and
+/// are dropped, and duplicated tick arrays are passed once. Fails if the first initialized tick
+/// array is missing from `tick_arrays`, which the program rejects.
+pub fn order_tick_arrays_for_swap(
+    program_id: &Pubkey,
+    pool_state: &PoolState,
+    tickarray_bitmap_extension: &Option<TickArrayBitmapExtension>,
+    tick_arrays: Vec<TickArrayState>,
+    zero_for_one: bool
+) -> Result<Vec<Pubkey>, ClientError> {
+    let (_, first_start_index) = first_initialized_tick_array(
+        pool_state,
+        tickarray_bitmap_extension,
+        zero_for_one
+    )?;
+    let mut tick_arrays = tick_arrays
+        .iter()
+        .map(|tick_array| (tick_array.start_tick_index, tick_array.pool_id))
+        .filter(|(start_index, _)| {
+            if zero_for_one {
+                *start_index <= first_start_index
+            } else {
+                *start_index >= first_start_index
+            }
+        })
+        .collect::<Vec<_>>();
+    if zero_for_one {
+        tick_arrays.sort_by(|a, b| b.cmp(a));
+    } else {
+        tick_arrays.sort();
+    }
+    tick_arrays.dedup();
+    match tick_arrays.first() {
+        Some((start_index, _)) if *start_index == first_start_index => {}
+        _ => {
+            return Err(
+                anyhow!(
+                    "the first initialized tick array {} of the swap is missing",
+                    first_start_index
+                ).into()
+            );
+        }
+    }
+    Ok(
+        tick_arrays
+            .into_iter()
+            .map(|(start_index, pool_id)| get_tick_array_key(program_id, &pool_id, start_index))
+            .collect()
+    )
+}
+
+/// Simulate an exact output swap against the given pool snapshot: `amount_in_consumed` of the
+/// returned quote is the input required to receive `amount_out`. Fails with
+/// `ClientError::InsufficientLiquidity` if the liquidity up to the price limit, or within
+/// `tick_arrays`, can't provide `amount_out`.
+pub fn compute_swap_quote_exact_out(
+    amm_config: &AmmConfig,
+    pool_state: &PoolState,
+    tick_arrays: &mut VecDeque<TickArrayState>,
+    tickarray_bitmap_extension: &Option<TickArrayBitmapExtension>,
+    amount_out: u64,
+    zero_for_one: bool,
+    sqrt_price_limit_x64: Option<u128>
+) -> Result<SwapQuote, ClientError> {
+    let (is_pool_current_tick_array, current_valid_tick_array_start_index) =
+        first_initialized_tick_array(pool_state, tickarray_bitmap_extension, zero_for_one)?;
+    if tick_arrays.is_empty() {
+        return Err(anyhow!("at least one tick array is required to quote").into());
+    }
+    let (quote, _) = swap_compute(
+        zero_for_one,
+        false,
+        is_pool_current_tick_array,
+        amm_config.trade_fee_rate,
+        amount_out,
+        current_valid_tick_array_start_index,
+        sqrt_price_limit_x64.unwrap_or(0),
+        pool_state,
+        tickarray_bitmap_extension,
+        tick_arrays
+    )?;
+    if quote.amount_out < amount_out {
+        return Err(ClientError::InsufficientLiquidity {
+            requested: amount_out,
+            available: quote.amount_out,
+        });
+    }
+    Ok(quote)
+}
+
+/// A `SwapQuote` along with the pool price before and after the swap.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct DetailedSwapQuote {
+    pub quote: SwapQuote,
+    pub sqrt_price_before_x64: u128,
+    pub sqrt_price_after_x64: u128,
+    /// Shortfall of the output valued at the pool price before the swap against the input, net
+    /// of the trade fee, as a fraction, e.g. 0.01 for 1%. Zero for an empty swap.
+    pub price_impact_pct: f64,
+}
+
+/// Same as `compute_swap_quote`, along with the price impact of the swap and the pool prices
+/// before and after it.
+pub fn compute_swap_quote_detailed(
+    amm_config: &AmmConfig,
+    pool_state: &PoolState,
+    tick_arrays: &mut VecDeque<TickArrayState>,
+    tickarray_bitmap_extension: &Option<TickArrayBitmapExtension>,
+    amount_in: u64,
+    zero_for_one: bool,
+    sqrt_price_limit_x64: Option<u128>
+) -> Result<DetailedSwapQuote, ClientError> {
+    let quote = compute_swap_quote(
+        amm_config,
+        pool_state,
+        tick_arrays,
+        tickarray_bitmap_extension,
+        amount_in,
+        zero_for_one,
+        sqrt_price_limit_x64,
+        None
+    )?;
+    let sqrt_price_before_x64 = pool_state.sqrt_price_x64;
+    // token1 per token0 in raw amounts, the same units as the quote
+    let mid_price = sqrt_price_x64_to_price(sqrt_price_before_x64, 0, 0);
+    let amount_in_net = (quote.amount_in_consumed - quote.fee_amount) as f64;
+    let amount_out = quote.amount_out as f64;
+    let price_impact_pct = if amount_in_net == 0.0 {
+        0.0
+    } else if zero_for_one {
+        1.0 - amount_out / (amount_in_net * mid_price)
+    } else {
+        1.0 - (amount_out * mid_price) / amount_in_net
+    };
+    Ok(DetailedSwapQuote {
+        quote,
+        sqrt_price_before_x64,
+        sqrt_price_after_x64: quote.sqrt_price_x64,
+        price_impact_pct,
+    })
+}
+
 fn swap_compute(
     zero_for_one: bool,
     is_base_input: bool,
@@ -339,11 +1730,11 @@ fn swap_compute(
     current_valid_tick_array_start_index: i32,
     sqrt_price_limit_x64: u128,
     pool_state: &PoolState,
-    tickarray_bitmap_extension: &TickArrayBitmapExtension,
+    tickarray_bitmap_extension: &Option<TickArrayBitmapExtension>,
     tick_arrays: &mut VecDeque<TickArrayState>
-) -> Result<(u64, VecDeque<i32>), &'static str> {
+) -> Result<(SwapQuote, VecDeque<i32>), ClientError> {
     if amount_specified == 0 {
-        return Result::Err("amountSpecified must not be 0");
+        return Err(anyhow!("amountSpecified must not be 0").into());
     }
     let sqrt_price_limit_x64 = if sqrt_price_limit_x64 == 0 {
         if zero_for_one {
@@ -356,17 +1747,17 @@ fn swap_compute(
     };
     if zero_for_one {
         if sqrt_price_limit_x64 < tick_math::MIN_SQRT_PRICE_X64 {
-            return Result::Err("sqrt_price_limit_x64 must greater than MIN_SQRT_PRICE_X64");
+            return Err(anyhow!("sqrt_price_limit_x64 must greater than MIN_SQRT_PRICE_X64").into());
         }
         if sqrt_price_limit_x64 >= pool_state.sqrt_price_x64 {
-            return Result::Err("sqrt_price_limit_x64 must smaller than current");
+            return Err(anyhow!("sqrt_price_limit_x64 must smaller than current").into());
         }
     } else {
         if sqrt_price_limit_x64 > tick_math::MAX_SQRT_PRICE_X64 {
-            return Result::Err("sqrt_price_limit_x64 must smaller than MAX_SQRT_PRICE_X64");
+            return Err(anyhow!("sqrt_price_limit_x64 must smaller than MAX_SQRT_PRICE_X64").into());
         }
         if sqrt_price_limit_x64 <= pool_state.sqrt_price_x64 {
-            return Result::Err("sqrt_price_limit_x64 must greater than current");
+            return Err(anyhow!("sqrt_price_limit_x64 must greater than current").into());
         }
     }
     let mut tick_match_current_tick_array = is_pool_current_tick_array;
@@ -379,13 +1770,34 @@ fn swap_compute(
         liquidity: pool_state.liquidity,
     };
 
-    let mut tick_array_current = tick_arrays.pop_front().unwrap();
-    if tick_array_current.start_tick_index != current_valid_tick_array_start_index {
-        return Result::Err("tick array start tick index does not match");
-    }
+    let mut amount_in_total: u64 = 0;
+    let mut amount_out_total: u64 = 0;
+    let mut fee_amount_total: u64 = 0;
+
+    // the swap can't go on once the tick arrays supplied run out, as the program would reject it
+    let insufficient_liquidity = |amount_specified_remaining: u64| {
+        ClientError::InsufficientLiquidity {
+            requested: amount_specified,
+            available: amount_specified - amount_specified_remaining,
+        }
+    };
+    // tick arrays ahead of the first initialized one are skipped, as the program does
+    let mut tick_array_current = loop {
+        let tick_array = tick_arrays
+            .pop_front()
+            .ok_or_else(||
+                anyhow!(
+                    "the first initialized tick array {} of the swap is missing",
+                    current_valid_tick_array_start_index
+                )
+            )?;
+        if tick_array.start_tick_index == current_valid_tick_array_start_index {
+            break tick_array;
+        }
+    };
+    let mut current_valid_tick_array_start_index = current_valid_tick_array_start_index;
     let mut tick_array_start_index_vec = VecDeque::new();
     tick_array_start_index_vec.push_back(tick_array_current.start_tick_index);
-    let mut loop_count = 0;
     // loop across ticks until input liquidity is consumed, or the limit price is reached
     while
         state.amount_specified_remaining != 0 &&
@@ -393,47 +1805,50 @@ fn swap_compute(
         state.tick < tick_math::MAX_TICK &&
         state.tick > tick_math::MIN_TICK
     {
-        if loop_count > 10 {
-            return Result::Err("loop_count limit");
-        }
         let mut step = StepComputations::default();
         step.sqrt_price_start_x64 = state.sqrt_price_x64;
         // save the bitmap, and the tick account if it is initialized
         let mut next_initialized_tick = if
             let Some(tick_state) = tick_array_current
                 .next_initialized_tick(state.tick, pool_state.tick_spacing, zero_for_one)
-                .unwrap()
+                .map_err(|e| anyhow!("{}", e))?
         {
             Box::new(*tick_state)
         } else {
             if !tick_match_current_tick_array {
                 tick_match_current_tick_array = true;
-                Box::new(*tick_array_current.first_initialized_tick(zero_for_one).unwrap())
+                Box::new(
+                    *tick_array_current
+                        .first_initialized_tick(zero_for_one)
+                        .map_err(|e| anyhow!("{}", e))?
+                )
             } else {
                 Box::new(TickState::default())
             }
         };
         if !next_initialized_tick.is_initialized() {
-            let current_valid_tick_array_start_index = pool_state
+            current_valid_tick_array_start_index = pool_state
                 .next_initialized_tick_array_start_index(
-                    &Some(*tickarray_bitmap_extension),
+                    tickarray_bitmap_extension,
                     current_valid_tick_array_start_index,
                     zero_for_one
                 )
-                .unwrap();
-            tick_array_current = tick_arrays.pop_front().unwrap();
-            if current_valid_tick_array_start_index.is_none() {
-                return Result::Err("tick array start tick index out of range limit");
-            }
-            if tick_array_current.start_tick_index != current_valid_tick_array_start_index.unwrap() {
-                return Result::Err("tick array start tick index does not match");
+                .map_err(|_|
+                    anyhow!("tick array bitmap extension is required past the default bitmap")
+                )?
+                // no initialized tick array is left in the swap direction
+                .ok_or_else(|| insufficient_liquidity(state.amount_specified_remaining))?;
+            while tick_array_current.start_tick_index != current_valid_tick_array_start_index {
+                tick_array_current = tick_arrays
+                    .pop_front()
+                    .ok_or_else(|| insufficient_liquidity(state.amount_specified_remaining))?;
             }
             tick_array_start_index_vec.push_back(tick_array_current.start_tick_index);
-            let mut first_initialized_tick = tick_array_current
+            let first_initialized_tick = tick_array_current
                 .first_initialized_tick(zero_for_one)
-                .unwrap();
+                .map_err(|e| anyhow!("{}", e))?;
 
-            next_initialized_tick = Box::new(*first_initialized_tick.deref_mut());
+            next_initialized_tick = Box::new(*first_initialized_tick);
         }
         step.tick_next = next_initialized_tick.tick;
         step.initialized = next_initialized_tick.is_initialized();
@@ -469,6 +1884,9 @@ fn swap_compute(
         step.amount_in = swap_step.amount_in;
         step.amount_out = swap_step.amount_out;
         step.fee_amount = swap_step.fee_amount;
+        amount_in_total = amount_in_total.checked_add(step.amount_in).unwrap();
+        amount_out_total = amount_out_total.checked_add(step.amount_out).unwrap();
+        fee_amount_total = fee_amount_total.checked_add(step.fee_amount).unwrap();
 
         if is_base_input {
             state.amount_specified_remaining = state.amount_specified_remaining
@@ -501,8 +1919,264 @@ fn swap_compute(
             // recompute unless we're on a lower tick boundary (i.e. already transitioned ticks), and haven't moved
             state.tick = tick_math::get_tick_at_sqrt_price(state.sqrt_price_x64).unwrap();
         }
-        loop_count += 1;
+    }
+    if
+        state.amount_specified_remaining != 0 &&
+        (state.tick >= tick_math::MAX_TICK || state.tick <= tick_math::MIN_TICK)
+    {
+        return Err(insufficient_liquidity(state.amount_specified_remaining));
+    }
+
+    Ok((
+        SwapQuote {
+            amount_out: amount_out_total,
+            amount_in_consumed: amount_in_total + fee_amount_total,
+            fee_amount: fee_amount_total,
+            sqrt_price_x64: state.sqrt_price_x64,
+            tick: state.tick,
+        },
+        tick_array_start_index_vec,
+    ))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::BTreeMap;
+
+    const TICK_LOWER: i32 = -6932;
+    const TICK_UPPER: i32 = 6932;
+
+    const LIQUIDITY: u128 = 1_000_000_000_000;
+
+    /// A pool at tick 0 with tick spacing 1 and three nested positions of `LIQUIDITY` over
+    /// [-10, 10], [-70, 70] and [-130, 130], along with its initialized tick arrays in zero for
+    /// one order.
+    fn build_swap_pool() -> (AmmConfig, PoolState, VecDeque<TickArrayState>) {
+        let mut amm_config = AmmConfig::default();
+        amm_config.trade_fee_rate = 2500;
+        let mut pool_state = PoolState::default();
+        pool_state.tick_spacing = 1;
+        pool_state.sqrt_price_x64 = tick_math::get_sqrt_price_at_tick(0).unwrap();
+        pool_state.liquidity = LIQUIDITY * 3;
+        let mut tick_arrays = BTreeMap::new();
+        for tick in [10, 70, 130] {
+            for (tick, liquidity_net) in [
+                (-tick, LIQUIDITY as i128),
+                (tick, -(LIQUIDITY as i128)),
+            ] {
+                let start_index = TickArrayState::get_array_start_index(tick, 1);
+                let tick_array = tick_arrays.entry(start_index).or_insert_with(|| {
+                    let mut tick_array = TickArrayState::default();
+                    tick_array.initialize(start_index, 1, Pubkey::default()).unwrap();
+                    pool_state.flip_tick_array_bit(None, start_index).unwrap();
+                    tick_array
+                });
+                let tick_state = tick_array.get_tick_state_mut(tick, 1).unwrap();
+                tick_state.tick = tick;
+                tick_state.liquidity_net = liquidity_net;
+                tick_state.liquidity_gross = LIQUIDITY;
+                tick_array.update_initialized_tick_count(true).unwrap();
+            }
+        }
+        let tick_arrays = tick_arrays
+            .into_values()
+            .rev()
+            .filter(|tick_array| tick_array.start_tick_index <= 0)
+            .collect();
+        (amm_config, pool_state, tick_arrays)
+    }
+
+    #[test]
+    fn swap_quote_across_tick_arrays_test() {
+        let (amm_config, pool_state, tick_arrays) = build_swap_pool();
+        let program_id = Pubkey::new_unique();
+        let pool_id = Pubkey::new_unique();
+        // crosses the ticks -10 and -70, heading for -130 in the fourth tick array
+        let amount_in = 8_000_000_000;
+        let quote = compute_swap_quote(
+            &amm_config,
+            &pool_state,
+            &mut tick_arrays.clone(),
+            &None,
+            amount_in,
+            true,
+            None,
+            None
+        ).unwrap();
+        assert_eq!(quote.amount_in_consumed, amount_in);
+        assert!(quote.tick < -70 && quote.tick > -130);
+        let keys = tick_arrays_for_swap(
+            &program_id,
+            &pool_id,
+            &amm_config,
+            &pool_state,
+            &None,
+            &mut tick_arrays.clone(),
+            amount_in,
+            true
+        ).unwrap();
+        let expected = [0, -60, -120, -180]
+            .into_iter()
+            .map(|start_index| get_tick_array_key(&program_id, &pool_id, start_index))
+            .collect::<Vec<_>>();
+        assert_eq!(keys, expected);
+    }
+
+    #[test]
+    fn swap_quote_exact_out_insufficient_liquidity_test() {
+        let (amm_config, pool_state, tick_arrays) = build_swap_pool();
+        // nothing is initialized past the default bitmap either
+        let tickarray_bitmap_extension = Some(TickArrayBitmapExtension::default());
+        // the output of the whole liquidity is short of 11e9
+        let amount_out = 20_000_000_000;
+        let result = compute_swap_quote_exact_out(
+            &amm_config,
+            &pool_state,
+            &mut tick_arrays.clone(),
+            &tickarray_bitmap_extension,
+            amount_out,
+            true,
+            None
+        );
+        match result {
+            Err(ClientError::InsufficientLiquidity { requested, available }) => {
+                assert_eq!(requested, amount_out);
+                assert!(available > 0 && available < amount_out);
+            }
+            other => panic!("expected InsufficientLiquidity, got {:?}", other),
+        }
+        // the same swap out of the first two tick arrays runs out of tick arrays
+        let amount_out = 8_000_000_000;
+        let quote = compute_swap_quote_exact_out(
+            &amm_config,
+            &pool_state,
+            &mut tick_arrays.clone(),
+            &tickarray_bitmap_extension,
+            amount_out,
+            true,
+            None
+        ).unwrap();
+        assert_eq!(quote.amount_out, amount_out);
+        let mut first_tick_arrays = tick_arrays.into_iter().take(2).collect();
+        let result = compute_swap_quote_exact_out(
+            &amm_config,
+            &pool_state,
+            &mut first_tick_arrays,
+            &tickarray_bitmap_extension,
+            amount_out,
+            true,
+            None
+        );
+        match result {
+            Err(ClientError::InsufficientLiquidity { requested, .. }) => {
+                assert_eq!(requested, amount_out);
+            }
+            other => panic!("expected InsufficientLiquidity, got {:?}", other),
+        }
+    }
+
+    fn assert_approx_eq(a: f64, b: f64) {
+        assert!((a - b).abs() < 1e-12, "{} != {}", a, b);
+    }
+
+    /// Token amounts per unit of liquidity deposited at `price`, within the test range.
+    fn amounts_at_entry(price: f64) -> (f64, f64) {
+        let sqrt_price_lower = Q_RATIO.powi(TICK_LOWER).sqrt();
+        let sqrt_price_upper = Q_RATIO.powi(TICK_UPPER).sqrt();
+        (1.0 / price.sqrt() - 1.0 / sqrt_price_upper, price.sqrt() - sqrt_price_lower)
+    }
+
+    #[test]
+    fn impermanent_loss_in_range_test() {
+        let price_lower = Q_RATIO.powi(TICK_LOWER);
+        let price_upper = Q_RATIO.powi(TICK_UPPER);
+        let entry_price = 1.0;
+        let k: f64 = 1.21;
+        // concentrated impermanent loss of a price move by k within [price_lower, price_upper)
+        let expected =
+            (2.0 * k.sqrt() - 1.0 - k) /
+            (1.0 + k - (price_lower / entry_price).sqrt() - k * (entry_price / price_upper).sqrt());
+        let loss = impermanent_loss(entry_price, entry_price * k, TICK_LOWER, TICK_UPPER);
+        assert_approx_eq(loss, expected);
+        // concentrating the liquidity amplifies the loss of a full range position
+        assert!(loss < 2.0 * k.sqrt() / (1.0 + k) - 1.0);
+    }
+
+    #[test]
+    fn impermanent_loss_below_range_test() {
+        let entry_price = 1.0;
+        let current_price = 0.25;
+        let (amount_0, amount_1) = amounts_at_entry(entry_price);
+        // the position is entirely token0, swapped at the prices of the range
+        let position_amount_0 =
+            1.0 / Q_RATIO.powi(TICK_LOWER).sqrt() - 1.0 / Q_RATIO.powi(TICK_UPPER).sqrt();
+        let expected =
+            (position_amount_0 * current_price) / (amount_0 * current_price + amount_1) - 1.0;
+        let loss = impermanent_loss(entry_price, current_price, TICK_LOWER, TICK_UPPER);
+        assert_approx_eq(loss, expected);
+        assert!(impermanent_loss(entry_price, 0.1, TICK_LOWER, TICK_UPPER) < loss);
+    }
+
+    #[test]
+    fn impermanent_loss_above_range_test() {
+        let entry_price = 1.0;
+        let current_price = 4.0;
+        let (amount_0, amount_1) = amounts_at_entry(entry_price);
+        // the position is entirely token1, swapped at the prices of the range
+        let position_amount_1 = Q_RATIO.powi(TICK_UPPER).sqrt() - Q_RATIO.powi(TICK_LOWER).sqrt();
+        let expected = position_amount_1 / (amount_0 * current_price + amount_1) - 1.0;
+        let loss = impermanent_loss(entry_price, current_price, TICK_LOWER, TICK_UPPER);
+        assert_approx_eq(loss, expected);
+        assert!(impermanent_loss(entry_price, 10.0, TICK_LOWER, TICK_UPPER) < loss);
+    }
+
+    #[test]
+    fn impermanent_loss_unchanged_price_test() {
+        for price in [0.25, 1.0, 1.5, 4.0] {
+            assert_approx_eq(impermanent_loss(price, price, TICK_LOWER, TICK_UPPER), 0.0);
+        }
+    }
+
+    #[test]
+    fn impermanent_loss_invalid_inputs_test() {
+        assert!(impermanent_loss(f64::NAN, 1.0, TICK_LOWER, TICK_UPPER).is_nan());
+        assert!(impermanent_loss(1.0, f64::NAN, TICK_LOWER, TICK_UPPER).is_nan());
+        assert!(impermanent_loss(f64::INFINITY, 1.0, TICK_LOWER, TICK_UPPER).is_nan());
+        assert!(impermanent_loss(0.0, 1.0, TICK_LOWER, TICK_UPPER).is_nan());
+        assert!(impermanent_loss(1.0, -1.0, TICK_LOWER, TICK_UPPER).is_nan());
+        assert!(impermanent_loss(1.0, 1.2, TICK_UPPER, TICK_LOWER).is_nan());
+    }
+
+    #[test]
+    fn price_to_tick_round_trip_test() {
+        for (decimals_0, decimals_1) in [(6, 9), (9, 6), (0, 0)] {
+            for tick in [tick_math::MIN_TICK, -100_000, -1, 0, 1, 12_345, tick_math::MAX_TICK] {
+                let price = tick_to_price(tick, decimals_0, decimals_1);
+                assert_eq!(price_to_tick(price, decimals_0, decimals_1).unwrap(), tick);
+            }
+        }
+        // at a raw price of 1, a whole token0 of 6 decimals is worth 0.001 of token1 of 9 decimals
+        assert_approx_eq(tick_to_price(0, 6, 9), 0.001);
+    }
+
+    #[test]
+    fn price_to_tick_nearest_test() {
+        let price = tick_to_price(100, 6, 9);
+        assert_eq!(price_to_tick(price * Q_RATIO.powf(0.4), 6, 9).unwrap(), 100);
+        assert_eq!(price_to_tick(price * Q_RATIO.powf(0.6), 6, 9).unwrap(), 101);
+        assert_eq!(price_to_tick(price / Q_RATIO.powf(0.4), 6, 9).unwrap(), 100);
+        assert_eq!(price_to_tick(price / Q_RATIO.powf(0.6), 6, 9).unwrap(), 99);
     }
 
-    Ok((state.amount_calculated, tick_array_start_index_vec))
+    #[test]
+    fn price_to_tick_invalid_price_test() {
+        let max_price = tick_to_price(tick_math::MAX_TICK, 0, 0);
+        let min_price = tick_to_price(tick_math::MIN_TICK, 0, 0);
+        assert!(price_to_tick(max_price * Q_RATIO, 0, 0).is_err());
+        assert!(price_to_tick(min_price / Q_RATIO, 0, 0).is_err());
+        for price in [0.0, -1.0, f64::NAN, f64::INFINITY] {
+            assert!(price_to_tick(price, 0, 0).is_err());
+        }
+    }
 }
@@ -1,28 +1,36 @@
 #![allow(dead_code)]
 
 // Re-export all the instruction modules and their functionality
+pub mod error;
 pub mod instructions;
+#[cfg(feature = "serde")]
+pub mod serde_pubkey;
 
 // Re-export commonly used types and functions from main.rs that might be useful
 use anchor_client::{ Client, Cluster };
 use anchor_lang::prelude::AccountMeta;
+use anchor_lang::{ AccountDeserialize, Discriminator };
 use anyhow::{ format_err, Result };
 use arrayref::array_ref;
 use configparser::ini::Ini;
 use solana_account_decoder::{
-    parse_token::{ TokenAccountType, UiAccountState },
+    parse_token::TokenAccountType,
     UiAccountData,
     UiAccountEncoding,
+    UiDataSliceConfig,
 };
 use solana_client::{
+    nonblocking::rpc_client::RpcClient as NonblockingRpcClient,
     rpc_client::RpcClient,
     rpc_config::{ RpcAccountInfoConfig, RpcProgramAccountsConfig, RpcTransactionConfig },
     rpc_filter::{ Memcmp, RpcFilterType },
     rpc_request::TokenAccountsFilter,
 };
 use solana_sdk::{
+    address_lookup_table::instruction::{ create_lookup_table, extend_lookup_table },
     commitment_config::CommitmentConfig,
-    compute_budget::ComputeBudgetInstruction,
+    compute_budget::{ self, ComputeBudgetInstruction },
+    instruction::Instruction,
     message::Message,
     program_pack::Pack,
     pubkey::Pubkey,
@@ -33,11 +41,22 @@ use solana_transaction_status::UiTransactionEncoding;
 use std::path::Path;
 use std::rc::Rc;
 use std::str::FromStr;
-use std::{ collections::VecDeque, convert::identity, mem::size_of };
+use std::collections::{ hash_map::Entry, HashMap };
+use std::time::Duration;
+use std::{ collections::VecDeque, convert::identity, mem::size_of, ops::Range };
 
 use raydium_amm_v3::{
-    libraries::{ fixed_point_64, liquidity_math, tick_math },
-    states::{ PoolState, TickArrayBitmapExtension, TickArrayState, POOL_TICK_ARRAY_BITMAP_SEED },
+    libraries::{ fixed_point_64, liquidity_math, tick_array_bit_map, tick_math },
+    states::{
+        AmmConfig,
+        PersonalPositionState,
+        PoolState,
+        ProtocolPositionState,
+        TickArrayBitmapExtension,
+        TickArrayState,
+        POOL_TICK_ARRAY_BITMAP_SEED,
+        POSITION_SEED,
+    },
 };
 use spl_associated_token_account::get_associated_token_address;
 use spl_token_2022::{
@@ -48,246 +67,1336 @@ use spl_token_2022::{
 use spl_token_client::token::ExtensionInitializationParams;
 
 // Re-export useful types and functions that other crates might need
+pub use error::ClientError;
 pub use instructions::utils::*;
 
 #[derive(Clone, Debug, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct ClientConfig {
     pub http_url: String,
     pub ws_url: String,
     pub payer_path: String,
     pub admin_path: String,
+    #[cfg_attr(feature = "serde", serde(with = "serde_pubkey"))]
     pub raydium_v3_program: Pubkey,
     pub slippage: f64,
+    /// Commitment used by the account fetchers, `confirmed` unless set by `Global.commitment`
+    pub commitment: CommitmentConfig,
+    /// Compute unit price prepended to the transactions sent by `send_or_build`
+    pub compute_unit_price_micro_lamports: Option<u64>,
+    /// Compute unit limit prepended to the transactions sent by `send_or_build`
+    pub compute_unit_limit: Option<u32>,
+    /// Timeout of the RPC requests of `build_rpc_client`, the `RpcClient` default when unset
+    pub rpc_timeout_secs: Option<u64>,
+    #[cfg_attr(feature = "serde", serde(with = "serde_pubkey"))]
     pub amm_config_key: Pubkey,
+    #[cfg_attr(feature = "serde", serde(default, with = "serde_pubkey::option"))]
     pub mint0: Option<Pubkey>,
+    #[cfg_attr(feature = "serde", serde(default, with = "serde_pubkey::option"))]
     pub mint1: Option<Pubkey>,
+    #[cfg_attr(feature = "serde", serde(default, with = "serde_pubkey::option"))]
     pub pool_id_account: Option<Pubkey>,
+    #[cfg_attr(feature = "serde", serde(default, with = "serde_pubkey::option"))]
     pub tickarray_bitmap_extension: Option<Pubkey>,
     pub amm_config_index: u16,
+    pub pools: Vec<PoolConfigEntry>,
+}
+
+impl ClientConfig {
+    pub fn pool_by_index(&self, i: usize) -> Option<&PoolConfigEntry> {
+        self.pools.get(i)
+    }
+
+    /// The compute budget instructions configured by `Global.compute_unit_limit` and
+    /// `Global.compute_unit_price_micro_lamports`, empty when neither is set.
+    pub fn compute_budget_instructions(&self) -> Vec<Instruction> {
+        let mut instructions = Vec::new();
+        if let Some(cu_limit) = self.compute_unit_limit {
+            instructions.push(ComputeBudgetInstruction::set_compute_unit_limit(cu_limit));
+        }
+        if let Some(micro_lamports) = self.compute_unit_price_micro_lamports {
+            instructions.push(ComputeBudgetInstruction::set_compute_unit_price(micro_lamports));
+        }
+        instructions
+    }
+
+    /// Prepend the configured compute budget instructions to `instructions`, unless they
+    /// already hold compute budget instructions, which take precedence.
+    pub fn apply_compute_budget(&self, instructions: &mut Vec<Instruction>) {
+        if
+            instructions
+                .iter()
+                .any(|instruction| instruction.program_id == compute_budget::id())
+        {
+            return;
+        }
+        instructions.splice(0..0, self.compute_budget_instructions());
+    }
+}
+
+/// Build the `RpcClient` of `config.http_url` with the configured commitment and
+/// `Global.rpc_timeout_secs`.
+pub fn build_rpc_client(config: &ClientConfig) -> RpcClient {
+    match config.rpc_timeout_secs {
+        Some(timeout_secs) =>
+            RpcClient::new_with_timeout_and_commitment(
+                config.http_url.clone(),
+                Duration::from_secs(timeout_secs),
+                config.commitment
+            ),
+        None => RpcClient::new_with_commitment(config.http_url.clone(), config.commitment),
+    }
+}
+
+/// A pool loaded from a `[Pool]`, `[Pool1]`, `[Pool2]`... section of the config file
+#[derive(Clone, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct PoolConfigEntry {
+    pub amm_config_index: u16,
+    #[cfg_attr(feature = "serde", serde(with = "serde_pubkey"))]
+    pub amm_config_key: Pubkey,
+    #[cfg_attr(feature = "serde", serde(default, with = "serde_pubkey::option"))]
+    pub mint0: Option<Pubkey>,
+    #[cfg_attr(feature = "serde", serde(default, with = "serde_pubkey::option"))]
+    pub mint1: Option<Pubkey>,
+    #[cfg_attr(feature = "serde", serde(default, with = "serde_pubkey::option"))]
+    pub pool_id_account: Option<Pubkey>,
+    #[cfg_attr(feature = "serde", serde(default, with = "serde_pubkey::option"))]
+    pub tickarray_bitmap_extension: Option<Pubkey>,
 }
 
 #[derive(Clone, Debug, PartialEq, Eq, Default)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct PoolAccounts {
+    #[cfg_attr(feature = "serde", serde(default, with = "serde_pubkey::option"))]
     pub pool_id: Option<Pubkey>,
+    #[cfg_attr(feature = "serde", serde(default, with = "serde_pubkey::option"))]
     pub pool_config: Option<Pubkey>,
+    #[cfg_attr(feature = "serde", serde(default, with = "serde_pubkey::option"))]
     pub pool_observation: Option<Pubkey>,
+    #[cfg_attr(feature = "serde", serde(with = "serde_pubkey::vec"))]
     pub pool_protocol_positions: Vec<Pubkey>,
+    #[cfg_attr(feature = "serde", serde(with = "serde_pubkey::vec"))]
     pub pool_personal_positions: Vec<Pubkey>,
+    #[cfg_attr(feature = "serde", serde(with = "serde_pubkey::vec"))]
     pub pool_tick_arrays: Vec<Pubkey>,
 }
 
+impl PoolAccounts {
+    /// Derive the accounts of a pool from its state. Positions can't be derived from the pool
+    /// state alone, so `pool_protocol_positions` and `pool_personal_positions` are left empty,
+    /// and `pool_tick_arrays` only covers the initialized tick arrays tracked by the pool's
+    /// default bitmap, not those tracked by the bitmap extension.
+    pub fn derive(pool_id: Pubkey, pool_state: &PoolState, program_id: &Pubkey) -> PoolAccounts {
+        let tick_count = TickArrayState::tick_count(pool_state.tick_spacing);
+        let tick_array_bitmap = pool_state.tick_array_bitmap;
+        let mut pool_tick_arrays = Vec::new();
+        for (word_index, word) in tick_array_bitmap.iter().enumerate() {
+            for bit_index in 0..64 {
+                if word & (1u64 << bit_index) == 0 {
+                    continue;
+                }
+                let offset = (word_index * 64 + bit_index) as i32;
+                let start_index =
+                    (offset - tick_array_bit_map::TICK_ARRAY_BITMAP_SIZE) * tick_count;
+                pool_tick_arrays.push(get_tick_array_key(program_id, &pool_id, start_index));
+            }
+        }
+        PoolAccounts {
+            pool_id: Some(pool_id),
+            pool_config: Some(pool_state.amm_config),
+            pool_observation: Some(pool_state.observation_key),
+            pool_protocol_positions: Vec::new(),
+            pool_personal_positions: Vec::new(),
+            pool_tick_arrays,
+        }
+    }
+}
+
 #[derive(Clone, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct PositionNftTokenInfo {
+    #[cfg_attr(feature = "serde", serde(with = "serde_pubkey"))]
     pub key: Pubkey,
+    #[cfg_attr(feature = "serde", serde(with = "serde_pubkey"))]
     pub program: Pubkey,
+    #[cfg_attr(feature = "serde", serde(with = "serde_pubkey"))]
     pub position: Pubkey,
+    #[cfg_attr(feature = "serde", serde(with = "serde_pubkey"))]
     pub mint: Pubkey,
     pub amount: u64,
     pub decimals: u8,
 }
 
-// Utility functions that might be useful for other crates
-pub fn load_cfg(client_config: &String) -> Result<ClientConfig> {
-    let mut config = Ini::new();
-    let _map = config.load(client_config).unwrap();
-    let http_url = config.get("Global", "http_url").unwrap();
-    if http_url.is_empty() {
-        panic!("http_url must not be empty");
+/// Memoizes pool related accounts by pubkey for the duration of a command, so that flows
+/// touching the same pool several times fetch each account only once. Call `clear` to
+/// invalidate the cached accounts.
+#[derive(Default)]
+pub struct AccountCache {
+    pools: HashMap<Pubkey, PoolState>,
+    amm_configs: HashMap<Pubkey, AmmConfig>,
+    tickarray_bitmap_extensions: HashMap<Pubkey, TickArrayBitmapExtension>,
+}
+
+impl AccountCache {
+    pub fn new() -> Self {
+        Self::default()
     }
-    let ws_url = config.get("Global", "ws_url").unwrap();
-    if ws_url.is_empty() {
-        panic!("ws_url must not be empty");
+
+    pub fn get_pool(
+        &mut self,
+        rpc_client: &RpcClient,
+        pool_id: &Pubkey
+    ) -> Result<&PoolState, ClientError> {
+        get_or_fetch_account(&mut self.pools, rpc_client, pool_id)
     }
-    let payer_path = config.get("Global", "payer_path").unwrap();
-    if payer_path.is_empty() {
-        panic!("payer_path must not be empty");
+
+    pub fn get_amm_config(
+        &mut self,
+        rpc_client: &RpcClient,
+        amm_config: &Pubkey
+    ) -> Result<&AmmConfig, ClientError> {
+        get_or_fetch_account(&mut self.amm_configs, rpc_client, amm_config)
     }
-    let admin_path = config.get("Global", "admin_path").unwrap();
-    if admin_path.is_empty() {
-        panic!("admin_path must not be empty");
+
+    pub fn get_tickarray_bitmap_extension(
+        &mut self,
+        rpc_client: &RpcClient,
+        tickarray_bitmap_extension: &Pubkey
+    ) -> Result<&TickArrayBitmapExtension, ClientError> {
+        get_or_fetch_account(
+            &mut self.tickarray_bitmap_extensions,
+            rpc_client,
+            tickarray_bitmap_extension
+        )
     }
 
-    let raydium_v3_program_str = config.get("Global", "raydium_v3_program").unwrap();
-    if raydium_v3_program_str.is_empty() {
-        panic!("raydium_v3_program must not be empty");
+    pub fn clear(&mut self) {
+        self.pools.clear();
+        self.amm_configs.clear();
+        self.tickarray_bitmap_extensions.clear();
     }
-    let raydium_v3_program = Pubkey::from_str(&raydium_v3_program_str).unwrap();
-    let slippage = config.getfloat("Global", "slippage").unwrap().unwrap();
+}
 
-    let mut mint0 = None;
-    let mint0_str = config.get("Pool", "mint0").unwrap();
-    if !mint0_str.is_empty() {
-        mint0 = Some(Pubkey::from_str(&mint0_str).unwrap());
+fn get_or_fetch_account<'a, T: AccountDeserialize + Discriminator>(
+    cache: &'a mut HashMap<Pubkey, T>,
+    rpc_client: &RpcClient,
+    key: &Pubkey
+) -> Result<&'a T, ClientError> {
+    match cache.entry(*key) {
+        Entry::Occupied(entry) => Ok(entry.into_mut()),
+        Entry::Vacant(entry) => {
+            let account = rpc_client.get_account(key)?;
+            Ok(entry.insert(deserialize_anchor_account::<T>(&account)?))
+        }
+    }
+}
+
+fn get_cfg_str(config: &Ini, section: &str, key: &str) -> Result<String> {
+    let value = config
+        .get(section, key)
+        .ok_or_else(|| format_err!("{}.{} is missing", section, key))?;
+    if value.is_empty() {
+        return Err(format_err!("{}.{} is empty", section, key));
     }
-    let mut mint1 = None;
-    let mint1_str = config.get("Pool", "mint1").unwrap();
-    if !mint1_str.is_empty() {
-        mint1 = Some(Pubkey::from_str(&mint1_str).unwrap());
+    Ok(value)
+}
+
+fn get_cfg_pubkey(config: &Ini, section: &str, key: &str) -> Result<Option<Pubkey>> {
+    match config.get(section, key) {
+        Some(value) if !value.is_empty() => Pubkey::from_str(&value)
+            .map(Some)
+            .map_err(|_| format_err!("{}.{} is not a valid pubkey", section, key)),
+        _ => Ok(None),
     }
-    let amm_config_index = config.getuint("Pool", "amm_config_index").unwrap().unwrap() as u16;
+}
+
+// Utility functions that might be useful for other crates
+pub fn load_cfg(client_config: &String) -> Result<ClientConfig, ClientError> {
+    parse_cfg(client_config).map_err(ClientError::ConfigError)
+}
+
+fn parse_cfg(client_config: &String) -> Result<ClientConfig> {
+    let mut config = Ini::new();
+    let _map = config
+        .load(client_config)
+        .map_err(|e| format_err!("failed to load config {}: {}", client_config, e))?;
+    let http_url = get_cfg_str(&config, "Global", "http_url")?;
+    let ws_url = get_cfg_str(&config, "Global", "ws_url")?;
+    let payer_path = get_cfg_str(&config, "Global", "payer_path")?;
+    let admin_path = get_cfg_str(&config, "Global", "admin_path")?;
+
+    let raydium_v3_program = get_cfg_pubkey(&config, "Global", "raydium_v3_program")?
+        .ok_or_else(|| format_err!("Global.raydium_v3_program is empty"))?;
+    let slippage = config
+        .getfloat("Global", "slippage")
+        .map_err(|_| format_err!("Global.slippage is not a valid float"))?
+        .ok_or_else(|| format_err!("Global.slippage is missing"))?;
+    if !(0.0..=1.0).contains(&slippage) {
+        return Err(format_err!(
+            "Global.slippage must be within [0.0, 1.0], got {}",
+            slippage
+        ));
+    }
+    let commitment = match config.get("Global", "commitment") {
+        Some(commitment) if !commitment.is_empty() =>
+            CommitmentConfig::from_str(&commitment).map_err(|_|
+                format_err!("Global.commitment is not a valid commitment: {}", commitment)
+            )?,
+        _ => CommitmentConfig::confirmed(),
+    };
+    let compute_unit_price_micro_lamports = config
+        .getuint("Global", "compute_unit_price_micro_lamports")
+        .map_err(|_| format_err!("Global.compute_unit_price_micro_lamports is not a valid u64"))?;
+    let compute_unit_limit = config
+        .getuint("Global", "compute_unit_limit")
+        .map_err(|_| format_err!("Global.compute_unit_limit is not a valid u32"))?
+        .map(|cu_limit| {
+            u32
+                ::try_from(cu_limit)
+                .ok()
+                .filter(|cu_limit| *cu_limit <= instructions::rpc::MAX_COMPUTE_UNIT_LIMIT)
+                .ok_or_else(||
+                    format_err!(
+                        "Global.compute_unit_limit must be at most {}, got {}",
+                        instructions::rpc::MAX_COMPUTE_UNIT_LIMIT,
+                        cu_limit
+                    )
+                )
+        })
+        .transpose()?;
+    let rpc_timeout_secs = config
+        .getuint("Global", "rpc_timeout_secs")
+        .map_err(|_| format_err!("Global.rpc_timeout_secs is not a valid u64"))?;
+
+    let mut pools = vec![load_pool_cfg(&config, "Pool", &raydium_v3_program)?];
+    loop {
+        let section = format!("Pool{}", pools.len());
+        if config.get(&section, "amm_config_index").is_none() {
+            break;
+        }
+        pools.push(load_pool_cfg(&config, &section, &raydium_v3_program)?);
+    }
+    let PoolConfigEntry {
+        amm_config_index,
+        amm_config_key,
+        mint0,
+        mint1,
+        pool_id_account,
+        tickarray_bitmap_extension,
+    } = pools[0].clone();
+
+    Ok(ClientConfig {
+        http_url,
+        ws_url,
+        payer_path,
+        admin_path,
+        raydium_v3_program,
+        slippage,
+        commitment,
+        compute_unit_price_micro_lamports,
+        compute_unit_limit,
+        rpc_timeout_secs,
+        amm_config_key,
+        mint0,
+        mint1,
+        pool_id_account,
+        tickarray_bitmap_extension,
+        amm_config_index,
+        pools,
+    })
+}
+
+fn load_pool_cfg(
+    config: &Ini,
+    section: &str,
+    raydium_v3_program: &Pubkey
+) -> Result<PoolConfigEntry> {
+    let mut mint0 = get_cfg_pubkey(config, section, "mint0")?;
+    let mut mint1 = get_cfg_pubkey(config, section, "mint1")?;
+    let amm_config_index = config
+        .getuint(section, "amm_config_index")
+        .ok()
+        .flatten()
+        .and_then(|index| u16::try_from(index).ok())
+        .ok_or_else(|| format_err!("{}.amm_config_index is not a valid u16", section))?;
 
     let (amm_config_key, __bump) = Pubkey::find_program_address(
         &[raydium_amm_v3::states::AMM_CONFIG_SEED.as_bytes(), &amm_config_index.to_be_bytes()],
-        &raydium_v3_program
+        raydium_v3_program
     );
 
-    let pool_id_account = if mint0 != None && mint1 != None {
-        if mint0.unwrap() > mint1.unwrap() {
-            let temp_mint = mint0;
-            mint0 = mint1;
-            mint1 = temp_mint;
-        }
+    let pool_id_account = if let (Some(m0), Some(m1)) = (mint0, mint1) {
+        let (m0, m1) = order_mints(m0, m1);
+        mint0 = Some(m0);
+        mint1 = Some(m1);
         Some(
             Pubkey::find_program_address(
                 &[
                     raydium_amm_v3::states::POOL_SEED.as_bytes(),
                     amm_config_key.to_bytes().as_ref(),
-                    mint0.unwrap().to_bytes().as_ref(),
-                    mint1.unwrap().to_bytes().as_ref(),
+                    m0.to_bytes().as_ref(),
+                    m1.to_bytes().as_ref(),
                 ],
-                &raydium_v3_program
-            ).0
-        )
-    } else {
-        None
-    };
-    let tickarray_bitmap_extension = if pool_id_account != None {
-        Some(
-            Pubkey::find_program_address(
-                &[
-                    POOL_TICK_ARRAY_BITMAP_SEED.as_bytes(),
-                    pool_id_account.unwrap().to_bytes().as_ref(),
-                ],
-                &raydium_v3_program
+                raydium_v3_program
             ).0
         )
     } else {
         None
     };
+    let tickarray_bitmap_extension = pool_id_account.map(|pool_id| {
+        Pubkey::find_program_address(
+            &[POOL_TICK_ARRAY_BITMAP_SEED.as_bytes(), pool_id.to_bytes().as_ref()],
+            raydium_v3_program
+        ).0
+    });
 
-    Ok(ClientConfig {
-        http_url,
-        ws_url,
-        payer_path,
-        admin_path,
-        raydium_v3_program,
-        slippage,
+    Ok(PoolConfigEntry {
+        amm_config_index,
         amm_config_key,
         mint0,
         mint1,
         pool_id_account,
         tickarray_bitmap_extension,
-        amm_config_index,
     })
 }
 
-pub fn read_keypair_file(s: &str) -> Result<Keypair> {
+pub fn read_keypair_file(s: &str) -> Result<Keypair, ClientError> {
     solana_sdk::signature
         ::read_keypair_file(s)
-        .map_err(|_| format_err!("failed to read keypair from {}", s))
+        .map_err(|_| format_err!("failed to read keypair from {}", s).into())
 }
 
-pub fn write_keypair_file(keypair: &Keypair, outfile: &str) -> Result<String> {
+pub fn write_keypair_file(keypair: &Keypair, outfile: &str) -> Result<String, ClientError> {
     solana_sdk::signature
         ::write_keypair_file(keypair, outfile)
-        .map_err(|_| format_err!("failed to write keypair to {}", outfile))
+        .map_err(|_| format_err!("failed to write keypair to {}", outfile).into())
 }
 
 pub fn path_is_exist(path: &str) -> bool {
     Path::new(path).exists()
 }
 
-pub fn load_cur_and_next_five_tick_array(
+pub fn get_tick_array_key(program_id: &Pubkey, pool_id: &Pubkey, start_index: i32) -> Pubkey {
+    Pubkey::find_program_address(
+        &[
+            raydium_amm_v3::states::TICK_ARRAY_SEED.as_bytes(),
+            pool_id.to_bytes().as_ref(),
+            &start_index.to_be_bytes(),
+        ],
+        program_id
+    ).0
+}
+
+/// Fetch the tick array bitmap extension of the pool. Pools created before the extension was
+/// introduced have none, which returns `None`, the pool's default bitmap alone is used then.
+pub fn load_tickarray_bitmap_extension(
     rpc_client: &RpcClient,
+    pool_config: &ClientConfig
+) -> Result<Option<TickArrayBitmapExtension>, ClientError> {
+    let tickarray_bitmap_extension = pool_config.tickarray_bitmap_extension.ok_or_else(||
+        format_err!("Pool.tickarray_bitmap_extension is missing")
+    )?;
+    let account = rpc_client.get_account_with_commitment(
+        &tickarray_bitmap_extension,
+        pool_config.commitment
+    )?.value;
+    match account {
+        Some(account) =>
+            Ok(
+                Some(
+                    instructions::utils::deserialize_anchor_account::<TickArrayBitmapExtension>(
+                        &account
+                    )?
+                )
+            ),
+        None => Ok(None),
+    }
+}
+
+/// Fetch the tick array bitmap extension of `pool_id` from its PDA, `None` when the pool
+/// predates the extension and the account doesn't exist.
+pub fn get_tick_array_bitmap_extension(
+    rpc_client: &RpcClient,
+    pool_id: &Pubkey,
+    program_id: &Pubkey
+) -> Result<Option<TickArrayBitmapExtension>, ClientError> {
+    let (tickarray_bitmap_extension, _) = Pubkey::find_program_address(
+        &[POOL_TICK_ARRAY_BITMAP_SEED.as_bytes(), pool_id.to_bytes().as_ref()],
+        program_id
+    );
+    let account = rpc_client.get_account_with_commitment(
+        &tickarray_bitmap_extension,
+        rpc_client.commitment()
+    )?.value;
+    match account {
+        Some(account) =>
+            Ok(Some(deserialize_anchor_account::<TickArrayBitmapExtension>(&account)?)),
+        None => Ok(None),
+    }
+}
+
+fn get_cur_and_next_tick_array_keys(
     pool_config: &ClientConfig,
     pool_state: &PoolState,
-    tickarray_bitmap_extension: &TickArrayBitmapExtension,
-    zero_for_one: bool
-) -> VecDeque<TickArrayState> {
-    let (_, mut current_valid_tick_array_start_index) = pool_state
-        .get_first_initialized_tick_array(&Some(*tickarray_bitmap_extension), zero_for_one)
-        .unwrap();
+    tickarray_bitmap_extension: &Option<TickArrayBitmapExtension>,
+    zero_for_one: bool,
+    count: usize
+) -> Result<Vec<Pubkey>> {
+    let pool_id = pool_config.pool_id_account.ok_or_else(||
+        format_err!("Pool.pool_id_account is missing")
+    )?;
+    let (_, mut current_valid_tick_array_start_index) =
+        instructions::utils::first_initialized_tick_array(
+            pool_state,
+            tickarray_bitmap_extension,
+            zero_for_one
+        )?;
     let mut tick_array_keys = Vec::new();
     tick_array_keys.push(
-        Pubkey::find_program_address(
-            &[
-                raydium_amm_v3::states::TICK_ARRAY_SEED.as_bytes(),
-                pool_config.pool_id_account.unwrap().to_bytes().as_ref(),
-                &current_valid_tick_array_start_index.to_be_bytes(),
-            ],
-            &pool_config.raydium_v3_program
-        ).0
+        get_tick_array_key(
+            &pool_config.raydium_v3_program,
+            &pool_id,
+            current_valid_tick_array_start_index
+        )
     );
-    let mut max_array_size = 5;
+    let mut max_array_size = count;
     while max_array_size != 0 {
         let next_tick_array_index = pool_state
             .next_initialized_tick_array_start_index(
-                &Some(*tickarray_bitmap_extension),
+                tickarray_bitmap_extension,
                 current_valid_tick_array_start_index,
                 zero_for_one
             )
-            .unwrap();
+            .map_err(|e| format_err!("{}", e))?;
         if next_tick_array_index.is_none() {
             break;
         }
         current_valid_tick_array_start_index = next_tick_array_index.unwrap();
         tick_array_keys.push(
-            Pubkey::find_program_address(
-                &[
-                    raydium_amm_v3::states::TICK_ARRAY_SEED.as_bytes(),
-                    pool_config.pool_id_account.unwrap().to_bytes().as_ref(),
-                    &current_valid_tick_array_start_index.to_be_bytes(),
-                ],
-                &pool_config.raydium_v3_program
-            ).0
+            get_tick_array_key(
+                &pool_config.raydium_v3_program,
+                &pool_id,
+                current_valid_tick_array_start_index
+            )
         );
         max_array_size -= 1;
     }
-    let tick_array_rsps = rpc_client.get_multiple_accounts(&tick_array_keys).unwrap();
+    Ok(tick_array_keys)
+}
+
+pub fn load_cur_and_next_five_tick_array(
+    rpc_client: &RpcClient,
+    pool_config: &ClientConfig,
+    pool_state: &PoolState,
+    tickarray_bitmap_extension: &Option<TickArrayBitmapExtension>,
+    zero_for_one: bool
+) -> Result<VecDeque<TickArrayState>, ClientError> {
+    load_cur_and_next_tick_arrays(
+        rpc_client,
+        pool_config,
+        pool_state,
+        tickarray_bitmap_extension,
+        zero_for_one,
+        5
+    )
+}
+
+/// Load the current tick array and up to `count` following initialized tick arrays in the
+/// swap direction, stopping early when there are no more initialized tick arrays.
+/// Each additional tick array is one more account fetched from RPC, so a large `count`
+/// increases RPC load.
+pub fn load_cur_and_next_tick_arrays(
+    rpc_client: &RpcClient,
+    pool_config: &ClientConfig,
+    pool_state: &PoolState,
+    tickarray_bitmap_extension: &Option<TickArrayBitmapExtension>,
+    zero_for_one: bool,
+    count: usize
+) -> Result<VecDeque<TickArrayState>, ClientError> {
+    let tick_array_keys = get_cur_and_next_tick_array_keys(
+        pool_config,
+        pool_state,
+        tickarray_bitmap_extension,
+        zero_for_one,
+        count
+    )?;
+    let tick_array_rsps = instructions::rpc::with_retry(3, Duration::from_millis(200), || {
+        instructions::rpc::get_multiple_accounts_chunked_with_commitment(
+            rpc_client,
+            &tick_array_keys,
+            pool_config.commitment
+        )
+    })?;
     let mut tick_arrays = VecDeque::new();
+    for (tick_array_key, tick_array) in tick_array_keys.iter().zip(tick_array_rsps) {
+        let tick_array = tick_array.ok_or_else(||
+            format_err!("tick array {} not found", tick_array_key)
+        )?;
+        let tick_array_state = instructions::utils::deserialize_anchor_account::<TickArrayState>(
+            &tick_array
+        )?;
+        tick_arrays.push_back(tick_array_state);
+    }
+    Ok(tick_arrays)
+}
+
+/// Load a window of initialized tick arrays around the current tick covering both swap
+/// directions, up to five tick arrays past the current one on each side, in ascending order of
+/// their start index. Tick arrays found in both directions are loaded once.
+pub fn load_tick_arrays_both_directions(
+    rpc_client: &RpcClient,
+    pool_config: &ClientConfig,
+    pool_state: &PoolState,
+    tickarray_bitmap_extension: &Option<TickArrayBitmapExtension>
+) -> Result<VecDeque<TickArrayState>, ClientError> {
+    let mut tick_array_keys = Vec::new();
+    for zero_for_one in [true, false] {
+        for key in get_cur_and_next_tick_array_keys(
+            pool_config,
+            pool_state,
+            tickarray_bitmap_extension,
+            zero_for_one,
+            5
+        )? {
+            if !tick_array_keys.contains(&key) {
+                tick_array_keys.push(key);
+            }
+        }
+    }
+    let tick_array_rsps = instructions::rpc::get_multiple_accounts_chunked_with_commitment(
+        rpc_client,
+        &tick_array_keys,
+        pool_config.commitment
+    )?;
+    let mut tick_arrays = Vec::new();
+    for (tick_array_key, tick_array) in tick_array_keys.iter().zip(tick_array_rsps) {
+        let tick_array = tick_array.ok_or_else(||
+            format_err!("tick array {} not found", tick_array_key)
+        )?;
+        tick_arrays.push(
+            instructions::utils::deserialize_anchor_account::<TickArrayState>(&tick_array)?
+        );
+    }
+    tick_arrays.sort_by_key(|tick_array| tick_array.start_tick_index);
+    Ok(tick_arrays.into())
+}
+
+/// Fetch the tick arrays of the pool starting at `start_indices`, in the same order. Tick arrays
+/// which aren't initialized are `None`.
+pub fn get_tick_arrays(
+    rpc_client: &RpcClient,
+    pool_id: &Pubkey,
+    start_indices: &[i32],
+    program_id: &Pubkey
+) -> Result<Vec<Option<TickArrayState>>, ClientError> {
+    let tick_array_keys: Vec<Pubkey> = start_indices
+        .iter()
+        .map(|start_index| get_tick_array_key(program_id, pool_id, *start_index))
+        .collect();
+    let tick_array_rsps = instructions::rpc::get_multiple_accounts_chunked(
+        rpc_client,
+        &tick_array_keys
+    )?;
+    let mut tick_arrays = Vec::with_capacity(tick_array_keys.len());
     for tick_array in tick_array_rsps {
-        let tick_array_state = instructions::utils
-            ::deserialize_anchor_account::<raydium_amm_v3::states::TickArrayState>(
-                &tick_array.unwrap()
-            )
-            .unwrap();
+        let tick_array_state = match tick_array {
+            Some(tick_array) => {
+                let tick_array = instructions::utils::deserialize_anchor_account::<TickArrayState>(
+                    &tick_array
+                )?;
+                Some(tick_array)
+            }
+            None => None,
+        };
+        tick_arrays.push(tick_array_state);
+    }
+    Ok(tick_arrays)
+}
+
+/// The state of a pool needed to quote swaps offline: the pool, its amm config, bitmap extension
+/// and all its initialized tick arrays, in ascending order of their start index.
+#[derive(Clone)]
+pub struct PoolSnapshot {
+    pub pool: PoolState,
+    pub amm_config: AmmConfig,
+    pub bitmap_ext: Option<TickArrayBitmapExtension>,
+    pub tick_arrays: Vec<TickArrayState>,
+    /// Slot the pool and bitmap extension were read at
+    pub slot: u64,
+}
+
+impl PoolSnapshot {
+    /// Quote an exact input swap against the snapshot, see `compute_swap_quote`.
+    pub fn simulate_swap(
+        &self,
+        amount_in: u64,
+        zero_for_one: bool,
+        sqrt_price_limit_x64: Option<u128>
+    ) -> Result<SwapQuote, ClientError> {
+        let (_, first_start_index) = instructions::utils::first_initialized_tick_array(
+            &self.pool,
+            &self.bitmap_ext,
+            zero_for_one
+        )?;
+        // the quote expects the tick arrays in swap order starting from the first initialized one
+        let mut tick_arrays: VecDeque<TickArrayState> = if zero_for_one {
+            self.tick_arrays
+                .iter()
+                .rev()
+                .filter(|tick_array| tick_array.start_tick_index <= first_start_index)
+                .copied()
+                .collect()
+        } else {
+            self.tick_arrays
+                .iter()
+                .filter(|tick_array| tick_array.start_tick_index >= first_start_index)
+                .copied()
+                .collect()
+        };
+        compute_swap_quote(
+            &self.amm_config,
+            &self.pool,
+            &mut tick_arrays,
+            &self.bitmap_ext,
+            amount_in,
+            zero_for_one,
+            sqrt_price_limit_x64,
+            None
+        )
+    }
+}
+
+/// Fetch a `PoolSnapshot` of `pool_id`. The pool and its bitmap extension are read at the same
+/// slot, the amm config and tick arrays are fetched right after and may be from a later slot.
+pub fn fetch_pool_snapshot(
+    rpc_client: &RpcClient,
+    pool_id: &Pubkey,
+    program_id: &Pubkey
+) -> Result<PoolSnapshot, ClientError> {
+    let (tickarray_bitmap_extension, _) = Pubkey::find_program_address(
+        &[POOL_TICK_ARRAY_BITMAP_SEED.as_bytes(), pool_id.to_bytes().as_ref()],
+        program_id
+    );
+    let response = rpc_client.get_multiple_accounts_with_commitment(
+        &[*pool_id, tickarray_bitmap_extension],
+        rpc_client.commitment()
+    )?;
+    let slot = response.context.slot;
+    let mut accounts = response.value.into_iter();
+    let pool_account = accounts
+        .next()
+        .flatten()
+        .ok_or_else(|| format_err!("pool {} not found", pool_id))?;
+    let pool = deserialize_anchor_account::<PoolState>(&pool_account)?;
+    let bitmap_ext = match accounts.next().flatten() {
+        Some(account) => Some(deserialize_anchor_account::<TickArrayBitmapExtension>(&account)?),
+        None => None,
+    };
+    let amm_config = deserialize_anchor_account::<AmmConfig>(
+        &rpc_client.get_account(&pool.amm_config)?
+    )?;
+    // pools without the extension can't have tick arrays past the default bitmap
+    let start_indices = list_initialized_tick_arrays(&pool, &bitmap_ext.unwrap_or_default());
+    let mut tick_arrays = Vec::with_capacity(start_indices.len());
+    for (start_index, tick_array) in start_indices
+        .iter()
+        .zip(get_tick_arrays(rpc_client, pool_id, &start_indices, program_id)?) {
+        tick_arrays.push(
+            tick_array.ok_or_else(||
+                format_err!("initialized tick array starting at {} not found", start_index)
+            )?
+        );
+    }
+    Ok(PoolSnapshot {
+        pool,
+        amm_config,
+        bitmap_ext,
+        tick_arrays,
+        slot,
+    })
+}
+
+/// The active liquidity of the pool over `tick_range`, for depth charts: `(tick, liquidity)`
+/// pairs where `liquidity` is active from `tick` up to the tick of the next pair, starting with
+/// the liquidity at `tick_range.start` followed by one pair per initialized tick in the range.
+/// The tick arrays between the current tick and the range are loaded to walk the liquidity from
+/// the current one.
+pub fn liquidity_distribution(
+    rpc_client: &RpcClient,
+    pool_id: &Pubkey,
+    program_id: &Pubkey,
+    tick_range: Range<i32>
+) -> Result<Vec<(i32, i128)>, ClientError> {
+    if
+        tick_range.is_empty() ||
+        tick_range.start < tick_math::MIN_TICK ||
+        tick_range.end > tick_math::MAX_TICK
+    {
+        return Err(
+            format_err!(
+                "tick range {:?} must be non empty and within [{}, {}]",
+                tick_range,
+                tick_math::MIN_TICK,
+                tick_math::MAX_TICK
+            ).into()
+        );
+    }
+    let pool = deserialize_anchor_account::<PoolState>(&rpc_client.get_account(pool_id)?)?;
+    let bitmap_ext = get_tick_array_bitmap_extension(rpc_client, pool_id, program_id)?;
+    let tick_current = pool.tick_current;
+    let tick_count = TickArrayState::tick_count(pool.tick_spacing);
+    let lowest_tick = tick_range.start.min(tick_current);
+    let highest_tick = (tick_range.end - 1).max(tick_current);
+    let start_indices: Vec<i32> = list_initialized_tick_arrays(
+        &pool,
+        &bitmap_ext.unwrap_or_default()
+    )
+        .into_iter()
+        .filter(|start_index| {
+            start_index + tick_count > lowest_tick && *start_index <= highest_tick
+        })
+        .collect();
+    let mut ticks = Vec::new();
+    for tick_array in get_tick_arrays(rpc_client, pool_id, &start_indices, program_id)?
+        .into_iter()
+        .flatten() {
+        let tick_states = tick_array.ticks;
+        ticks.extend(
+            tick_states
+                .iter()
+                .filter(|tick_state| tick_state.is_initialized())
+                .map(|tick_state| (tick_state.tick, tick_state.liquidity_net))
+        );
+    }
+    ticks.sort_by_key(|(tick, _)| *tick);
+
+    // the pool liquidity is active at the current tick, the liquidity below the loaded ticks
+    // follows by removing the net liquidity of the loaded ticks at or below the current one
+    let mut liquidity = pool.liquidity as i128;
+    for (_, liquidity_net) in ticks.iter().filter(|(tick, _)| *tick <= tick_current) {
+        liquidity -= liquidity_net;
+    }
+    let mut distribution = Vec::new();
+    let mut ticks = ticks.into_iter().peekable();
+    while let Some((_, liquidity_net)) = ticks.next_if(|(tick, _)| *tick <= tick_range.start) {
+        liquidity += liquidity_net;
+    }
+    distribution.push((tick_range.start, liquidity));
+    for (tick, liquidity_net) in ticks.take_while(|(tick, _)| *tick < tick_range.end) {
+        liquidity += liquidity_net;
+        distribution.push((tick, liquidity));
+    }
+    Ok(distribution)
+}
+
+pub async fn load_cur_and_next_five_tick_array_async(
+    rpc_client: &NonblockingRpcClient,
+    pool_config: &ClientConfig,
+    pool_state: &PoolState,
+    tickarray_bitmap_extension: &Option<TickArrayBitmapExtension>,
+    zero_for_one: bool
+) -> Result<VecDeque<TickArrayState>, ClientError> {
+    load_cur_and_next_tick_arrays_async(
+        rpc_client,
+        pool_config,
+        pool_state,
+        tickarray_bitmap_extension,
+        zero_for_one,
+        5
+    ).await
+}
+
+/// Async variant of `load_cur_and_next_tick_arrays`, a large `count` increases RPC load.
+pub async fn load_cur_and_next_tick_arrays_async(
+    rpc_client: &NonblockingRpcClient,
+    pool_config: &ClientConfig,
+    pool_state: &PoolState,
+    tickarray_bitmap_extension: &Option<TickArrayBitmapExtension>,
+    zero_for_one: bool,
+    count: usize
+) -> Result<VecDeque<TickArrayState>, ClientError> {
+    let tick_array_keys = get_cur_and_next_tick_array_keys(
+        pool_config,
+        pool_state,
+        tickarray_bitmap_extension,
+        zero_for_one,
+        count
+    )?;
+    let tick_array_rsps = rpc_client.get_multiple_accounts_with_commitment(
+        &tick_array_keys,
+        pool_config.commitment
+    ).await?.value;
+    let mut tick_arrays = VecDeque::new();
+    for (tick_array_key, tick_array) in tick_array_keys.iter().zip(tick_array_rsps) {
+        let tick_array = tick_array.ok_or_else(||
+            format_err!("tick array {} not found", tick_array_key)
+        )?;
+        let tick_array_state = instructions::utils::deserialize_anchor_account::<TickArrayState>(
+            &tick_array
+        )?;
         tick_arrays.push_back(tick_array_state);
     }
-    tick_arrays
+    Ok(tick_arrays)
+}
+
+/// Find the pools of a mint pair across all amm configs. Only the pool ids are fetched.
+pub fn find_pools_for_mints(
+    rpc_client: &RpcClient,
+    mint0: Pubkey,
+    mint1: Pubkey,
+    program_id: &Pubkey
+) -> Result<Vec<Pubkey>, ClientError> {
+    let (mint0, mint1) = order_mints(mint0, mint1);
+    // discriminator, bump, amm_config, owner
+    let token_mint_0_offset = 8 + 1 + 32 + 32;
+    let token_mint_1_offset = token_mint_0_offset + 32;
+    let config = RpcProgramAccountsConfig {
+        filters: Some(
+            vec![
+                RpcFilterType::DataSize(PoolState::LEN as u64),
+                RpcFilterType::Memcmp(
+                    Memcmp::new_raw_bytes(token_mint_0_offset, mint0.to_bytes().to_vec())
+                ),
+                RpcFilterType::Memcmp(
+                    Memcmp::new_raw_bytes(token_mint_1_offset, mint1.to_bytes().to_vec())
+                )
+            ]
+        ),
+        account_config: RpcAccountInfoConfig {
+            encoding: Some(UiAccountEncoding::Base64),
+            data_slice: Some(UiDataSliceConfig { offset: 0, length: 0 }),
+            ..RpcAccountInfoConfig::default()
+        },
+        ..RpcProgramAccountsConfig::default()
+    };
+    let pools = rpc_client.get_program_accounts_with_config(program_id, config)?;
+    Ok(
+        pools
+            .into_iter()
+            .map(|(pool_id, _)| pool_id)
+            .collect()
+    )
+}
+
+/// Fetch all the pools of the amm config `amm_config`, i.e. of a fee tier.
+pub fn list_pools_for_config(
+    rpc_client: &RpcClient,
+    amm_config: Pubkey,
+    program_id: &Pubkey
+) -> Result<Vec<(Pubkey, PoolState)>, ClientError> {
+    // discriminator, bump
+    let amm_config_offset = 8 + 1;
+    let config = RpcProgramAccountsConfig {
+        filters: Some(
+            vec![
+                RpcFilterType::DataSize(PoolState::LEN as u64),
+                RpcFilterType::Memcmp(
+                    Memcmp::new_raw_bytes(amm_config_offset, amm_config.to_bytes().to_vec())
+                )
+            ]
+        ),
+        account_config: RpcAccountInfoConfig {
+            encoding: Some(UiAccountEncoding::Base64),
+            ..RpcAccountInfoConfig::default()
+        },
+        ..RpcProgramAccountsConfig::default()
+    };
+    let pools = rpc_client.get_program_accounts_with_config(program_id, config)?;
+    let mut pool_states = Vec::with_capacity(pools.len());
+    for (pool_id, account) in pools.iter() {
+        pool_states.push((*pool_id, deserialize_anchor_account::<PoolState>(account)?));
+    }
+    Ok(pool_states)
+}
+
+/// The quote of a swap routed through two pools sharing `intermediate_mint`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct TwoHopQuote {
+    pub intermediate_mint: Pubkey,
+    pub first_zero_for_one: bool,
+    pub second_zero_for_one: bool,
+    /// Quote of `pool_a`, its `amount_out` is the input of `pool_b`
+    pub first: SwapQuote,
+    pub second: SwapQuote,
+    pub amount_in: u64,
+    pub amount_out: u64,
+}
+
+/// Quote an exact input swap of `pool_a`'s mint that isn't shared with `pool_b`, through
+/// `pool_a` into the shared mint and then through `pool_b`. The pools must share exactly one
+/// mint. Transfer fees of Token-2022 mints are accounted for on each transfer.
+pub fn quote_two_hop(
+    rpc_client: &RpcClient,
+    config: &ClientConfig,
+    pool_a: &Pubkey,
+    pool_b: &Pubkey,
+    amount_in: u64
+) -> Result<TwoHopQuote, ClientError> {
+    let pool_state_a = fetch_pool_state(rpc_client, config, pool_a)?;
+    let pool_state_b = fetch_pool_state(rpc_client, config, pool_b)?;
+    let mints_a = [pool_state_a.token_mint_0, pool_state_a.token_mint_1];
+    let mints_b = [pool_state_b.token_mint_0, pool_state_b.token_mint_1];
+    let shared_mints = mints_a
+        .iter()
+        .filter(|mint| mints_b.contains(mint))
+        .copied()
+        .collect::<Vec<Pubkey>>();
+    if shared_mints.len() != 1 {
+        return Err(
+            format_err!(
+                "pools {} and {} must share exactly one mint, they share {}",
+                pool_a,
+                pool_b,
+                shared_mints.len()
+            ).into()
+        );
+    }
+    let intermediate_mint = shared_mints[0];
+    // pool_a swaps into the shared mint, pool_b swaps out of it
+    let first_zero_for_one = pool_state_a.token_mint_1 == intermediate_mint;
+    let second_zero_for_one = pool_state_b.token_mint_0 == intermediate_mint;
+
+    let first = quote_pool_swap(
+        rpc_client,
+        config,
+        pool_a,
+        &pool_state_a,
+        amount_in,
+        first_zero_for_one
+    )?;
+    let second = quote_pool_swap(
+        rpc_client,
+        config,
+        pool_b,
+        &pool_state_b,
+        first.amount_out,
+        second_zero_for_one
+    )?;
+    Ok(TwoHopQuote {
+        intermediate_mint,
+        first_zero_for_one,
+        second_zero_for_one,
+        first,
+        second,
+        amount_in,
+        amount_out: second.amount_out,
+    })
+}
+
+fn fetch_pool_state(
+    rpc_client: &RpcClient,
+    config: &ClientConfig,
+    pool_id: &Pubkey
+) -> Result<PoolState> {
+    let pool_account = rpc_client
+        .get_account_with_commitment(pool_id, config.commitment)?
+        .value
+        .ok_or_else(|| format_err!("pool {} not found", pool_id))?;
+    if pool_account.owner != config.raydium_v3_program {
+        return Err(
+            format_err!(
+                "pool {} is not owned by program {}",
+                pool_id,
+                config.raydium_v3_program
+            )
+        );
+    }
+    Ok(deserialize_anchor_account::<PoolState>(&pool_account)?)
+}
+
+/// Quote an exact input swap on the given pool, loading its amm config, bitmap extension,
+/// tick arrays in the swap direction and mints, whose transfer fees are accounted for.
+fn quote_pool_swap(
+    rpc_client: &RpcClient,
+    config: &ClientConfig,
+    pool_id: &Pubkey,
+    pool_state: &PoolState,
+    amount_in: u64,
+    zero_for_one: bool
+) -> Result<SwapQuote> {
+    let pool_config = ClientConfig {
+        pool_id_account: Some(*pool_id),
+        tickarray_bitmap_extension: Some(
+            Pubkey::find_program_address(
+                &[POOL_TICK_ARRAY_BITMAP_SEED.as_bytes(), pool_id.to_bytes().as_ref()],
+                &config.raydium_v3_program
+            ).0
+        ),
+        ..config.clone()
+    };
+    let amm_config = deserialize_anchor_account::<AmmConfig>(
+        &rpc_client.get_account(&pool_state.amm_config)?
+    )?;
+    let tickarray_bitmap_extension = get_tick_array_bitmap_extension(
+        rpc_client,
+        pool_id,
+        &config.raydium_v3_program
+    )?;
+    let mut tick_arrays = load_cur_and_next_five_tick_array(
+        rpc_client,
+        &pool_config,
+        pool_state,
+        &tickarray_bitmap_extension,
+        zero_for_one
+    )?;
+    let mint_accounts = instructions::rpc::get_multiple_accounts_chunked(
+        rpc_client,
+        &[pool_state.token_mint_0, pool_state.token_mint_1]
+    )?;
+    let mut mint_states = Vec::with_capacity(mint_accounts.len());
+    for account in mint_accounts.iter() {
+        let account = account.as_ref().ok_or_else(|| format_err!("pool mint not found"))?;
+        mint_states.push(StateWithExtensions::<Mint>::unpack(&account.data)?);
+    }
+    let transfer_fees = SwapTransferFees {
+        mint_0: &mint_states[0],
+        mint_1: &mint_states[1],
+        epoch: rpc_client.get_epoch_info()?.epoch,
+    };
+    Ok(
+        compute_swap_quote(
+            &amm_config,
+            pool_state,
+            &mut tick_arrays,
+            &tickarray_bitmap_extension,
+            amount_in,
+            zero_for_one,
+            None,
+            Some(&transfer_fees)
+        )?
+    )
+}
+
+/// Max number of addresses an address lookup table holds.
+pub const MAX_LOOKUP_TABLE_ADDRESSES: usize = 256;
+/// Addresses added per `extend_lookup_table` transaction, keeping it within the size limit.
+const LOOKUP_TABLE_EXTEND_CHUNK: usize = 20;
+
+/// Create an address lookup table owned by `authority` for the pool of `pool_config`, holding
+/// the pool, its amm config, observation, vaults, mints, bitmap extension, the programs a swap
+/// uses and the pool's initialized tick arrays, nearest to the current tick first when they
+/// don't all fit. The table can be used by `build_versioned_swap` from the next slot on.
+pub fn create_pool_lookup_table(
+    rpc_client: &RpcClient,
+    authority: &Keypair,
+    pool_config: &ClientConfig
+) -> Result<Pubkey, ClientError> {
+    let program_id = pool_config.raydium_v3_program;
+    let pool_id = pool_config.pool_id_account.ok_or_else(||
+        format_err!("Pool.pool_id_account is missing")
+    )?;
+    let tickarray_bitmap_extension_key = pool_config.tickarray_bitmap_extension.ok_or_else(||
+        format_err!("Pool.tickarray_bitmap_extension is missing")
+    )?;
+    let pool_state = fetch_pool_state(rpc_client, pool_config, &pool_id)?;
+    let tickarray_bitmap_extension = load_tickarray_bitmap_extension(rpc_client, pool_config)?;
+
+    let mut addresses = vec![
+        program_id,
+        pool_id,
+        pool_state.amm_config,
+        pool_state.observation_key,
+        pool_state.token_vault_0,
+        pool_state.token_vault_1,
+        pool_state.token_mint_0,
+        pool_state.token_mint_1,
+        tickarray_bitmap_extension_key,
+        spl_token::id(),
+        spl_token_2022::id(),
+        spl_memo::id()
+    ];
+    let tick_current = pool_state.tick_current;
+    let mut tick_array_start_indexes = list_initialized_tick_arrays(
+        &pool_state,
+        &tickarray_bitmap_extension.unwrap_or_default()
+    );
+    tick_array_start_indexes.sort_by_key(|start_index|
+        (*start_index as i64).abs_diff(tick_current as i64)
+    );
+    addresses.extend(
+        tick_array_start_indexes
+            .into_iter()
+            .take(MAX_LOOKUP_TABLE_ADDRESSES - addresses.len())
+            .map(|start_index| get_tick_array_key(&program_id, &pool_id, start_index))
+    );
+
+    let recent_slot = rpc_client.get_slot_with_commitment(CommitmentConfig::finalized())?;
+    let (create_instruction, lookup_table) = create_lookup_table(
+        authority.pubkey(),
+        authority.pubkey(),
+        recent_slot
+    );
+    let mut table_instructions = vec![create_instruction];
+    for chunk in addresses.chunks(LOOKUP_TABLE_EXTEND_CHUNK) {
+        table_instructions.push(
+            extend_lookup_table(
+                lookup_table,
+                authority.pubkey(),
+                Some(authority.pubkey()),
+                chunk.to_vec()
+            )
+        );
+    }
+    // the table must exist before it can be extended, send one instruction per transaction
+    for instruction in table_instructions {
+        let transaction = Transaction::new_signed_with_payer(
+            &[instruction],
+            Some(&authority.pubkey()),
+            &[authority],
+            rpc_client.get_latest_blockhash()?
+        );
+        instructions::rpc::send_txn(rpc_client, &transaction, true)?;
+    }
+    Ok(lookup_table)
+}
+
+/// Fetch the protocol position of the pool for the range [tick_lower, tick_upper].
+pub fn get_protocol_position(
+    rpc_client: &RpcClient,
+    pool_id: &Pubkey,
+    tick_lower: i32,
+    tick_upper: i32,
+    program_id: &Pubkey
+) -> Result<ProtocolPositionState, ClientError> {
+    let (protocol_position_key, __bump) = Pubkey::find_program_address(
+        &[
+            POSITION_SEED.as_bytes(),
+            pool_id.to_bytes().as_ref(),
+            &tick_lower.to_be_bytes(),
+            &tick_upper.to_be_bytes(),
+        ],
+        program_id
+    );
+    let protocol_position_account = rpc_client.get_account(&protocol_position_key)?;
+    if protocol_position_account.owner != *program_id {
+        return Err(
+            format_err!(
+                "protocol position {} is not owned by program {}",
+                protocol_position_key,
+                program_id
+            ).into()
+        );
+    }
+    deserialize_anchor_account::<ProtocolPositionState>(&protocol_position_account)
+}
+
+/// The liquidity of a protocol position compared to the sum of the personal positions in the
+/// same range, see `reconcile_protocol_position`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct ProtocolPositionReconciliation {
+    pub protocol_liquidity: u128,
+    pub personal_liquidity: u128,
+    pub personal_position_count: usize,
+}
+
+impl ProtocolPositionReconciliation {
+    pub fn is_consistent(&self) -> bool {
+        self.protocol_liquidity == self.personal_liquidity
+    }
+}
+
+/// Sum the liquidity of all personal positions of the pool in [tick_lower, tick_upper] and
+/// compare it with the liquidity of the protocol position of that range.
+pub fn reconcile_protocol_position(
+    rpc_client: &RpcClient,
+    pool_id: &Pubkey,
+    tick_lower: i32,
+    tick_upper: i32,
+    program_id: &Pubkey
+) -> Result<ProtocolPositionReconciliation, ClientError> {
+    let protocol_position = get_protocol_position(
+        rpc_client,
+        pool_id,
+        tick_lower,
+        tick_upper,
+        program_id
+    )?;
+    // discriminator, bump, nft_mint
+    let pool_id_offset = 8 + 1 + 32;
+    let tick_lower_offset = pool_id_offset + 32;
+    let tick_upper_offset = tick_lower_offset + 4;
+    let config = RpcProgramAccountsConfig {
+        filters: Some(
+            vec![
+                RpcFilterType::DataSize(PersonalPositionState::LEN as u64),
+                RpcFilterType::Memcmp(
+                    Memcmp::new_raw_bytes(pool_id_offset, pool_id.to_bytes().to_vec())
+                ),
+                RpcFilterType::Memcmp(
+                    Memcmp::new_raw_bytes(tick_lower_offset, tick_lower.to_le_bytes().to_vec())
+                ),
+                RpcFilterType::Memcmp(
+                    Memcmp::new_raw_bytes(tick_upper_offset, tick_upper.to_le_bytes().to_vec())
+                )
+            ]
+        ),
+        account_config: RpcAccountInfoConfig {
+            encoding: Some(UiAccountEncoding::Base64),
+            ..RpcAccountInfoConfig::default()
+        },
+        ..RpcProgramAccountsConfig::default()
+    };
+    let personal_positions = rpc_client.get_program_accounts_with_config(program_id, config)?;
+    let mut personal_liquidity = 0u128;
+    for (personal_position_key, account) in personal_positions.iter() {
+        let personal_position = deserialize_anchor_account::<PersonalPositionState>(account)?;
+        personal_liquidity = personal_liquidity
+            .checked_add(personal_position.liquidity)
+            .ok_or_else(|| {
+                format_err!("liquidity overflow at personal position {}", personal_position_key)
+            })?;
+    }
+    Ok(ProtocolPositionReconciliation {
+        protocol_liquidity: protocol_position.liquidity,
+        personal_liquidity,
+        personal_position_count: personal_positions.len(),
+    })
 }
 
 pub fn get_all_nft_and_position_by_owner(
     client: &RpcClient,
     owner: &Pubkey,
     raydium_amm_v3_program: &Pubkey
-) -> Vec<PositionNftTokenInfo> {
+) -> Result<Vec<PositionNftTokenInfo>, ClientError> {
     let mut spl_nfts = get_nft_account_and_position_by_owner(
         client,
         owner,
         spl_token::id(),
-        raydium_amm_v3_program
-    );
+        raydium_amm_v3_program,
+        None,
+        client.commitment()
+    )?;
     let spl_2022_nfts = get_nft_account_and_position_by_owner(
         client,
         owner,
         spl_token_2022::id(),
-        raydium_amm_v3_program
-    );
+        raydium_amm_v3_program,
+        None,
+        client.commitment()
+    )?;
     spl_nfts.extend(spl_2022_nfts);
-    spl_nfts
+    Ok(spl_nfts)
 }
 
 pub fn get_nft_account_and_position_by_owner(
     client: &RpcClient,
     owner: &Pubkey,
     token_program: Pubkey,
-    raydium_amm_v3_program: &Pubkey
-) -> Vec<PositionNftTokenInfo> {
-    let all_tokens = client
-        .get_token_accounts_by_owner(owner, TokenAccountsFilter::ProgramId(token_program))
-        .unwrap();
+    raydium_amm_v3_program: &Pubkey,
+    limit: Option<usize>,
+    commitment: CommitmentConfig
+) -> Result<Vec<PositionNftTokenInfo>, ClientError> {
+    let all_tokens = client.get_token_accounts_by_owner_with_commitment(
+        owner,
+        TokenAccountsFilter::ProgramId(token_program),
+        commitment
+    )?.value;
     let mut position_nft_accounts = Vec::new();
     for keyed_account in all_tokens {
+        if limit.is_some_and(|limit| position_nft_accounts.len() >= limit) {
+            break;
+        }
         if let UiAccountData::Json(parsed_account) = keyed_account.account.data {
             if parsed_account.program == "spl-token" || parsed_account.program == "spl-token-2022" {
                 if
@@ -295,23 +1404,42 @@ pub fn get_nft_account_and_position_by_owner(
                         parsed_account.parsed
                     )
                 {
-                    let _frozen = ui_token_account.state == UiAccountState::Frozen;
-
-                    let token = ui_token_account.mint
-                        .parse::<Pubkey>()
-                        .unwrap_or_else(|err| panic!("Invalid mint: {}", err));
-                    let token_account = keyed_account.pubkey
-                        .parse::<Pubkey>()
-                        .unwrap_or_else(|err| panic!("Invalid token account: {}", err));
-                    let token_amount = ui_token_account.token_amount.amount
-                        .parse::<u64>()
-                        .unwrap_or_else(|err| panic!("Invalid token amount: {}", err));
-
-                    let _close_authority = ui_token_account.close_authority.map_or(*owner, |s| {
-                        s.parse::<Pubkey>().unwrap_or_else(|err|
-                            panic!("Invalid close authority: {}", err)
-                        )
-                    });
+                    let token_account = match keyed_account.pubkey.parse::<Pubkey>() {
+                        Ok(token_account) => token_account,
+                        Err(err) => {
+                            log::warn!(
+                                target: "raydium_client",
+                                "skip token account {}, invalid pubkey: {}",
+                                keyed_account.pubkey,
+                                err
+                            );
+                            continue;
+                        }
+                    };
+                    let token = match ui_token_account.mint.parse::<Pubkey>() {
+                        Ok(token) => token,
+                        Err(err) => {
+                            log::warn!(
+                                target: "raydium_client",
+                                "skip token account {}, invalid mint: {}",
+                                token_account,
+                                err
+                            );
+                            continue;
+                        }
+                    };
+                    let token_amount = match ui_token_account.token_amount.amount.parse::<u64>() {
+                        Ok(token_amount) => token_amount,
+                        Err(err) => {
+                            log::warn!(
+                                target: "raydium_client",
+                                "skip token account {}, invalid token amount: {}",
+                                token_account,
+                                err
+                            );
+                            continue;
+                        }
+                    };
 
                     if ui_token_account.token_amount.decimals == 0 && token_amount == 1 {
                         let (position_pda, _) = Pubkey::find_program_address(
@@ -334,5 +1462,125 @@ pub fn get_nft_account_and_position_by_owner(
             }
         }
     }
-    position_nft_accounts
+    Ok(position_nft_accounts)
+}
+
+/// Load the positions of `owner` along with their personal position state, fetching all the
+/// personal position accounts in batches of `getMultipleAccounts`. Nfts without a personal
+/// position account owned by `program_id` aren't positions and are skipped.
+pub fn get_positions_with_state(
+    rpc_client: &RpcClient,
+    owner: &Pubkey,
+    program_id: &Pubkey
+) -> Result<Vec<(PositionNftTokenInfo, PersonalPositionState)>, ClientError> {
+    let positions = get_all_nft_and_position_by_owner(rpc_client, owner, program_id)?;
+    fetch_position_states(rpc_client, &positions, program_id)
+}
+
+fn fetch_position_states(
+    rpc_client: &RpcClient,
+    positions: &[PositionNftTokenInfo],
+    program_id: &Pubkey
+) -> Result<Vec<(PositionNftTokenInfo, PersonalPositionState)>, ClientError> {
+    let position_keys: Vec<Pubkey> = positions
+        .iter()
+        .map(|position| position.position)
+        .collect();
+    let accounts = instructions::rpc::get_multiple_accounts_chunked(rpc_client, &position_keys)?;
+    let mut position_states = Vec::new();
+    for (position, account) in positions.iter().zip(accounts) {
+        let Some(account) = account.filter(|account| account.owner == *program_id) else {
+            continue;
+        };
+        let personal_position = deserialize_anchor_account::<PersonalPositionState>(&account)?;
+        position_states.push((position.clone(), personal_position));
+    }
+    Ok(position_states)
+}
+
+/// Keep the positions of `positions` opened in `pool_id`, fetching their personal position
+/// accounts in one batch. Nfts without a personal position account owned by `program_id` aren't
+/// positions and are dropped.
+pub fn filter_positions_by_pool(
+    rpc_client: &RpcClient,
+    positions: &[PositionNftTokenInfo],
+    pool_id: &Pubkey,
+    program_id: &Pubkey
+) -> Result<Vec<PositionNftTokenInfo>, ClientError> {
+    Ok(
+        fetch_position_states(rpc_client, positions, program_id)?
+            .into_iter()
+            .filter(|(_, personal_position)| personal_position.pool_id == *pool_id)
+            .map(|(position, _)| position)
+            .collect()
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Write a config of a pool of `mint0` and `mint1` to a temporary file and load it.
+    fn load_pool_cfg_of(name: &str, mint0: &Pubkey, mint1: &Pubkey) -> ClientConfig {
+        let path = std::env::temp_dir().join(format!("raydium_client_{}.ini", name));
+        std::fs
+            ::write(
+                &path,
+                format!(
+                    "[Global]\nhttp_url = http://127.0.0.1:8899\nws_url = ws://127.0.0.1:8900\n\
+                     payer_path = id.json\nadmin_path = admin.json\n\
+                     raydium_v3_program = {}\nslippage = 0.01\n\n\
+                     [Pool]\nmint0 = {}\nmint1 = {}\namm_config_index = 1\n",
+                    raydium_amm_v3::id(),
+                    mint0,
+                    mint1
+                )
+            )
+            .unwrap();
+        let config = load_cfg(&path.to_string_lossy().to_string()).unwrap();
+        std::fs::remove_file(&path).unwrap();
+        config
+    }
+
+    #[test]
+    fn order_mints_test() {
+        let a = Pubkey::new_unique();
+        let b = Pubkey::new_unique();
+        assert_eq!(order_mints(a, b), order_mints(b, a));
+        let (mint0, mint1) = order_mints(a, b);
+        assert!(mint0 < mint1);
+        assert_eq!(order_mints(a, a), (a, a));
+    }
+
+    #[test]
+    fn order_mints_pool_id_matches_load_cfg_test() {
+        let a = Pubkey::new_unique();
+        let b = Pubkey::new_unique();
+        let (mint0, mint1) = order_mints(b, a);
+        let program_id = raydium_amm_v3::id();
+        let (amm_config_key, __bump) = Pubkey::find_program_address(
+            &[raydium_amm_v3::states::AMM_CONFIG_SEED.as_bytes(), &(1u16).to_be_bytes()],
+            &program_id
+        );
+        let (pool_id, __bump) = Pubkey::find_program_address(
+            &[
+                raydium_amm_v3::states::POOL_SEED.as_bytes(),
+                amm_config_key.to_bytes().as_ref(),
+                mint0.to_bytes().as_ref(),
+                mint1.to_bytes().as_ref(),
+            ],
+            &program_id
+        );
+        // the configured mints are ordered whatever their order in the config file
+        for (name, config_mint0, config_mint1) in [
+            ("order_mints_ab", a, b),
+            ("order_mints_ba", b, a),
+        ] {
+            let config = load_pool_cfg_of(name, &config_mint0, &config_mint1);
+            assert_eq!(config.mint0, Some(mint0));
+            assert_eq!(config.mint1, Some(mint1));
+            assert_eq!(config.amm_config_key, amm_config_key);
+            assert_eq!(config.pool_id_account, Some(pool_id));
+        }
+    }
 }
@@ -107,4 +107,12 @@ pub enum ErrorCode {
     CalculateOverflow,
     #[msg("TransferFee calculate not match")]
     TransferFeeCalculateNotMatch,
+    #[msg("Invalid support mint input account number")]
+    InvalidSupportMintAccountNumber,
+    #[msg("Support mint associated account already initialized")]
+    SupportMintAssociatedAlreadyInitialized,
+    #[msg("Token_2022 mint extension is not allowed for support mint")]
+    NotAllowedMintExtension,
+    #[msg("Support mint is used by a pool")]
+    SupportMintInUse,
 }
@@ -0,0 +1,71 @@
+use crate::error::ErrorCode;
+use crate::states::*;
+use anchor_lang::{prelude::*, system_program};
+
+#[derive(Accounts)]
+pub struct CloseSupportMintAssociated<'info> {
+    /// Same authority as `create_support_mint_associated`, receives the rent
+    #[account(
+        mut,
+        constraint = (owner.key() == crate::admin::ID || owner.key() == crate::create_support_mint_associated_owner::ID) @ ErrorCode::NotApproved
+    )]
+    pub owner: Signer<'info>,
+    /// CHECK: Support token mint, only used to derive the support mint state address, so the
+    /// support of a closed mint can be removed as well.
+    pub token_mint: UncheckedAccount<'info>,
+    /// CHECK: Support mint state account, may be created before `support_mint_flags` was added,
+    /// so it's deserialized in contract.
+    #[account(
+        mut,
+        seeds = [
+            SUPPORT_MINT_SEED.as_bytes(),
+            token_mint.key().as_ref(),
+        ],
+        bump,
+        owner = crate::id()
+    )]
+    pub support_mint_associated: UncheckedAccount<'info>,
+}
+
+/// `remaining_accounts` holds the pools of the mint, as vault or reward mint, known to the caller.
+/// The close is rejected if any of them uses the mint, so the support is only removed once no pool
+/// depends on it.
+pub fn close_support_mint_associated<'a, 'b, 'c: 'info, 'info>(
+    ctx: Context<'a, 'b, 'c, 'info, CloseSupportMintAssociated<'info>>,
+) -> Result<()> {
+    let support_mint_info = ctx.accounts.support_mint_associated.to_account_info();
+    let support_mint_state =
+        SupportMintAssociated::try_deserialize_compat(&support_mint_info.try_borrow_data()?)?;
+    let mint = support_mint_state.mint;
+    for (index, pool_info) in ctx.remaining_accounts.iter().enumerate() {
+        let pool_state_loader = AccountLoader::<PoolState>::try_from(pool_info)?;
+        let pool_state = pool_state_loader.load()?;
+        let reward_infos = pool_state.reward_infos;
+        if pool_state.token_mint_0 == mint
+            || pool_state.token_mint_1 == mint
+            || reward_infos
+                .iter()
+                .any(|reward_info| reward_info.token_mint == mint)
+        {
+            msg!("support mint is used by the pool at index {}", index);
+            return err!(ErrorCode::SupportMintInUse);
+        }
+    }
+
+    let owner_info = ctx.accounts.owner.to_account_info();
+    let rent_lamports = support_mint_info.lamports();
+    **owner_info.try_borrow_mut_lamports()? = owner_info
+        .lamports()
+        .checked_add(rent_lamports)
+        .ok_or(ErrorCode::CalculateOverflow)?;
+    **support_mint_info.try_borrow_mut_lamports()? = 0;
+    support_mint_info.assign(&system_program::ID);
+    support_mint_info.resize(0)?;
+
+    emit!(CloseSupportMintAssociatedEvent {
+        mint,
+        by: ctx.accounts.owner.key(),
+    });
+
+    Ok(())
+}
@@ -11,7 +11,7 @@ pub struct CollectFundFee<'info> {
     #[account(constraint = (owner.key() == amm_config.fund_owner || owner.key() == crate::admin::ID) @ ErrorCode::NotApproved)]
     pub owner: Signer<'info>,
 
-    /// Pool state stores accumulated protocol fee amount
+    /// Pool state stores accumulated fund fee amount
     #[account(mut)]
     pub pool_state: AccountLoader<'info, PoolState>,
 
@@ -45,11 +45,11 @@ pub struct CollectFundFee<'info> {
     )]
     pub vault_1_mint: Box<InterfaceAccount<'info, Mint>>,
 
-    /// The address that receives the collected token_0 protocol fees
+    /// The address that receives the collected token_0 fund fees
     #[account(mut)]
     pub recipient_token_account_0: Box<InterfaceAccount<'info, TokenAccount>>,
 
-    /// The address that receives the collected token_1 protocol fees
+    /// The address that receives the collected token_1 fund fees
     #[account(mut)]
     pub recipient_token_account_1: Box<InterfaceAccount<'info, TokenAccount>>,
 
@@ -101,7 +101,7 @@ pub fn collect_fund_fee(
         &ctx.accounts.token_vault_1.to_account_info(),
     )?;
 
-    emit!(CollectProtocolFeeEvent {
+    emit!(CollectFundFeeEvent {
         pool_state: ctx.accounts.pool_state.key(),
         recipient_token_account_0: ctx.accounts.recipient_token_account_0.key(),
         recipient_token_account_1: ctx.accounts.recipient_token_account_1.key(),
@@ -1,7 +1,9 @@
 use crate::error::ErrorCode;
 use crate::states::*;
+use crate::util::{check_support_mint_extensions, create_or_allocate_account};
 use anchor_lang::prelude::*;
 use anchor_spl::{token_2022, token_interface::Mint};
+use spl_token_2022::extension::StateWithExtensions;
 use std::ops::DerefMut;
 
 pub mod create_support_mint_associated_owner {
@@ -41,10 +43,98 @@ pub struct CreateSupportMintAssociated<'info> {
     pub system_program: Program<'info, System>,
 }
 
+/// Mints carrying extensions the pools can't handle are rejected, see `check_support_mint_extensions`
+/// for the allowed extensions.
 pub fn create_support_mint_associated(ctx: Context<CreateSupportMintAssociated>) -> Result<()> {
+    check_support_mint_extensions(&ctx.accounts.token_mint.to_account_info())?;
     let support_mint_state = ctx.accounts.support_mint_associated.deref_mut();
     support_mint_state.bump = ctx.bumps.support_mint_associated;
     support_mint_state.mint = ctx.accounts.token_mint.key();
 
+    emit!(CreateSupportMintAssociatedEvent {
+        mint: support_mint_state.mint,
+        by: ctx.accounts.owner.key(),
+    });
+
+    Ok(())
+}
+
+#[derive(Accounts)]
+pub struct CreateSupportMintAssociatedBatch<'info> {
+    /// Address to be set as protocol owner.
+    #[account(
+        mut,
+        constraint = (owner.key() == crate::admin::ID || owner.key() == crate::create_support_mint_associated_owner::ID) @ ErrorCode::NotApproved
+    )]
+    pub owner: Signer<'info>,
+
+    pub system_program: Program<'info, System>,
+}
+
+/// `remaining_accounts` holds N token mints followed by their N support mint associated accounts.
+pub fn create_support_mint_associated_batch<'a, 'b, 'c: 'info, 'info>(
+    ctx: Context<'a, 'b, 'c, 'info, CreateSupportMintAssociatedBatch<'info>>,
+) -> Result<()> {
+    let remaining_accounts = ctx.remaining_accounts;
+    if remaining_accounts.is_empty() || remaining_accounts.len() % 2 != 0 {
+        return err!(ErrorCode::InvalidSupportMintAccountNumber);
+    }
+    let (token_mints, support_mint_associateds) =
+        remaining_accounts.split_at(remaining_accounts.len() / 2);
+    for (index, (token_mint_info, support_mint_info)) in token_mints
+        .iter()
+        .zip(support_mint_associateds.iter())
+        .enumerate()
+    {
+        if *token_mint_info.owner != token_2022::ID
+            || StateWithExtensions::<spl_token_2022::state::Mint>::unpack(
+                &token_mint_info.try_borrow_data()?,
+            )
+            .is_err()
+        {
+            msg!("invalid token mint at index {}", index);
+            return err!(ErrorCode::NotApproved);
+        }
+        check_support_mint_extensions(token_mint_info).map_err(|e| {
+            msg!("not allowed token mint at index {}", index);
+            e
+        })?;
+        let (expect_pda_address, bump) = Pubkey::find_program_address(
+            &[SUPPORT_MINT_SEED.as_bytes(), token_mint_info.key.as_ref()],
+            &crate::id(),
+        );
+        if expect_pda_address != support_mint_info.key() {
+            msg!("invalid support mint associated account at index {}", index);
+            return err!(ErrorCode::NotApproved);
+        }
+        if *support_mint_info.owner == crate::id() {
+            msg!(
+                "support mint associated account at index {} already initialized",
+                index
+            );
+            return err!(ErrorCode::SupportMintAssociatedAlreadyInitialized);
+        }
+        create_or_allocate_account(
+            &crate::id(),
+            ctx.accounts.owner.to_account_info(),
+            ctx.accounts.system_program.to_account_info(),
+            support_mint_info.clone(),
+            &[
+                SUPPORT_MINT_SEED.as_bytes(),
+                token_mint_info.key.as_ref(),
+                &[bump],
+            ],
+            SupportMintAssociated::LEN,
+        )?;
+        let mut support_mint_state = SupportMintAssociated::default();
+        support_mint_state.initialize(bump, token_mint_info.key())?;
+        support_mint_state.try_serialize(&mut &mut support_mint_info.try_borrow_mut_data()?[..])?;
+
+        emit!(CreateSupportMintAssociatedEvent {
+            mint: token_mint_info.key(),
+            by: ctx.accounts.owner.key(),
+        });
+    }
+
     Ok(())
 }
@@ -24,3 +24,9 @@ pub use update_pool_status::*;
 
 pub mod create_support_mint_associated;
 pub use create_support_mint_associated::*;
+
+pub mod update_support_mint_associated;
+pub use update_support_mint_associated::*;
+
+pub mod close_support_mint_associated;
+pub use close_support_mint_associated::*;
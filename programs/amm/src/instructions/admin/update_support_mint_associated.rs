@@ -0,0 +1,66 @@
+use crate::error::ErrorCode;
+use crate::states::*;
+use anchor_lang::{prelude::*, system_program};
+use anchor_spl::token_interface::Mint;
+
+#[derive(Accounts)]
+pub struct UpdateSupportMintAssociated<'info> {
+    /// Same authority as `create_support_mint_associated`
+    #[account(
+        mut,
+        constraint = (owner.key() == crate::admin::ID || owner.key() == crate::create_support_mint_associated_owner::ID) @ ErrorCode::NotApproved
+    )]
+    pub owner: Signer<'info>,
+    /// Support token mint
+    pub token_mint: InterfaceAccount<'info, Mint>,
+    /// CHECK: Support mint state account, may be created before `support_mint_flags` was added,
+    /// so it's deserialized and migrated in contract.
+    #[account(
+        mut,
+        seeds = [
+            SUPPORT_MINT_SEED.as_bytes(),
+            token_mint.key().as_ref(),
+        ],
+        bump,
+        owner = crate::id()
+    )]
+    pub support_mint_associated: UncheckedAccount<'info>,
+
+    pub system_program: Program<'info, System>,
+}
+
+pub fn update_support_mint_associated(
+    ctx: Context<UpdateSupportMintAssociated>,
+    support_mint_flags: u8,
+) -> Result<()> {
+    let support_mint_info = ctx.accounts.support_mint_associated.to_account_info();
+    let mut support_mint_state =
+        SupportMintAssociated::try_deserialize_compat(&support_mint_info.try_borrow_data()?)?;
+
+    // migrate the account created before `support_mint_flags` was added
+    if support_mint_info.data_len() < SupportMintAssociated::LEN {
+        let required_lamports = Rent::get()?
+            .minimum_balance(SupportMintAssociated::LEN)
+            .saturating_sub(support_mint_info.lamports());
+        if required_lamports > 0 {
+            let cpi_accounts = system_program::Transfer {
+                from: ctx.accounts.owner.to_account_info(),
+                to: support_mint_info.clone(),
+            };
+            let cpi_context =
+                CpiContext::new(ctx.accounts.system_program.to_account_info(), cpi_accounts);
+            system_program::transfer(cpi_context, required_lamports)?;
+        }
+        support_mint_info.resize(SupportMintAssociated::LEN)?;
+    }
+
+    support_mint_state.support_mint_flags = support_mint_flags;
+    support_mint_state.try_serialize(&mut &mut support_mint_info.try_borrow_mut_data()?[..])?;
+
+    emit!(UpdateSupportMintAssociatedEvent {
+        mint: support_mint_state.mint,
+        support_mint_flags,
+    });
+
+    Ok(())
+}
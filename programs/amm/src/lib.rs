@@ -75,6 +75,38 @@ pub mod amm_v3 {
         instructions::create_support_mint_associated(ctx)
     }
 
+    /// Create support token22 mint accounts for multiple mints in one instruction.
+    /// `remaining_accounts` holds N token mints followed by their N support mint associated accounts.
+    pub fn create_support_mint_associated_batch<'a, 'b, 'c: 'info, 'info>(
+        ctx: Context<'a, 'b, 'c, 'info, CreateSupportMintAssociatedBatch<'info>>,
+    ) -> Result<()> {
+        instructions::create_support_mint_associated_batch(ctx)
+    }
+
+    /// Update the status flags of a support token22 mint account, a disabled mint is treated
+    /// as not supported. Accounts created before the flags were added are migrated to the new size.
+    ///
+    /// # Arguments
+    ///
+    /// * `ctx`- The context of accounts
+    /// * `support_mint_flags`- The new status flags, bit 0 disables the mint
+    ///
+    pub fn update_support_mint_associated(
+        ctx: Context<UpdateSupportMintAssociated>,
+        support_mint_flags: u8,
+    ) -> Result<()> {
+        instructions::update_support_mint_associated(ctx, support_mint_flags)
+    }
+
+    /// Close a support token22 mint account and return its rent to the owner, the mint is no
+    /// longer supported when creating pools and rewards.
+    /// `remaining_accounts` holds the pools to check, the close fails if any of them uses the mint.
+    pub fn close_support_mint_associated<'a, 'b, 'c: 'info, 'info>(
+        ctx: Context<'a, 'b, 'c, 'info, CloseSupportMintAssociated<'info>>,
+    ) -> Result<()> {
+        instructions::close_support_mint_associated(ctx)
+    }
+
     /// Updates the owner of the amm config
     /// Must be called by the current owner or admin
     ///
@@ -727,6 +727,26 @@ pub struct CollectProtocolFeeEvent {
     pub amount_1: u64,
 }
 
+/// Emitted when the collected fund fees are withdrawn by the fund owner
+#[event]
+#[cfg_attr(feature = "client", derive(Debug))]
+pub struct CollectFundFeeEvent {
+    /// The pool whose fund fee is collected
+    pub pool_state: Pubkey,
+
+    /// The address that receives the collected token_0 fund fees
+    pub recipient_token_account_0: Pubkey,
+
+    /// The address that receives the collected token_1 fund fees
+    pub recipient_token_account_1: Pubkey,
+
+    /// The amount of token_0 fund fees that is withdrawn
+    pub amount_0: u64,
+
+    /// The amount of token_1 fund fees that is withdrawn
+    pub amount_1: u64,
+}
+
 /// Emitted by when a swap is performed for a pool
 #[event]
 #[cfg_attr(feature = "client", derive(Debug))]
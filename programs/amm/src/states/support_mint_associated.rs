@@ -2,6 +2,11 @@ use anchor_lang::prelude::*;
 
 pub const SUPPORT_MINT_SEED: &str = "support_mint";
 
+/// Bit index of `support_mint_flags`, a set bit disables the support of the mint
+pub enum SupportMintFlagBitIndex {
+    Disable,
+}
+
 /// Holds the current owner of the factory
 #[account]
 #[derive(Default, Debug)]
@@ -10,15 +15,106 @@ pub struct SupportMintAssociated {
     pub bump: u8,
     /// Address of the supported token22 mint
     pub mint: Pubkey,
+    /// Status flags of the support mint, see `SupportMintFlagBitIndex`
+    pub support_mint_flags: u8,
     pub padding: [u64; 8],
 }
 
 impl SupportMintAssociated {
-    pub const LEN: usize = 8 + 1 + 32 + 64;
+    pub const LEN: usize = 8 + 1 + 32 + 1 + 64;
+    /// Size of the accounts created before `support_mint_flags` was added
+    pub const LEGACY_LEN: usize = 8 + 1 + 32 + 64;
 
     pub fn initialize<'info>(&mut self, bump: u8, mint: Pubkey) -> Result<()> {
         self.bump = bump;
         self.mint = mint;
+        self.support_mint_flags = 0;
         Ok(())
     }
+
+    /// Deserialize the account data, accounts created before `support_mint_flags` was added
+    /// are zero extended, so their flags default to zero.
+    pub fn try_deserialize_compat(data: &[u8]) -> Result<Self> {
+        if data.len() >= Self::LEN {
+            return Self::try_deserialize(&mut &data[..]);
+        }
+        let mut data = data.to_vec();
+        data.resize(Self::LEN, 0);
+        Self::try_deserialize(&mut data.as_slice())
+    }
+
+    /// Get flag by bit, return true if the bit is not set
+    pub fn get_flag_by_bit(&self, bit: SupportMintFlagBitIndex) -> bool {
+        let flag = 1u8 << (bit as u8);
+        self.support_mint_flags & flag == 0
+    }
+}
+
+/// Emitted when a mint is added to the supported token22 mints
+#[event]
+#[cfg_attr(feature = "client", derive(Debug))]
+pub struct CreateSupportMintAssociatedEvent {
+    /// The supported token22 mint
+    pub mint: Pubkey,
+    /// The authority that added the mint
+    pub by: Pubkey,
+}
+
+/// Emitted when a mint is removed from the supported token22 mints
+#[event]
+#[cfg_attr(feature = "client", derive(Debug))]
+pub struct CloseSupportMintAssociatedEvent {
+    /// The token22 mint no longer supported
+    pub mint: Pubkey,
+    /// The authority that removed the mint
+    pub by: Pubkey,
+}
+
+/// Emitted when the flags of a support mint are updated
+#[event]
+#[cfg_attr(feature = "client", derive(Debug))]
+pub struct UpdateSupportMintAssociatedEvent {
+    /// The supported token22 mint
+    pub mint: Pubkey,
+    /// The new status flags of the support mint
+    pub support_mint_flags: u8,
+}
+
+#[cfg(test)]
+mod support_mint_associated_test {
+    use super::*;
+
+    #[test]
+    fn deserialize_legacy_account_test() {
+        let mint = Pubkey::new_unique();
+        let mut data = Vec::with_capacity(SupportMintAssociated::LEGACY_LEN);
+        data.extend_from_slice(&SupportMintAssociated::DISCRIMINATOR);
+        data.push(254);
+        data.extend_from_slice(mint.as_ref());
+        data.resize(SupportMintAssociated::LEGACY_LEN, 0);
+
+        let support_mint = SupportMintAssociated::try_deserialize_compat(&data).unwrap();
+        assert_eq!(support_mint.bump, 254);
+        assert_eq!(support_mint.mint, mint);
+        assert_eq!(support_mint.support_mint_flags, 0);
+        assert!(support_mint.get_flag_by_bit(SupportMintFlagBitIndex::Disable));
+    }
+
+    #[test]
+    fn deserialize_account_test() {
+        let support_mint = SupportMintAssociated {
+            bump: 254,
+            mint: Pubkey::new_unique(),
+            support_mint_flags: 1,
+            padding: [0; 8],
+        };
+        let mut data = Vec::with_capacity(SupportMintAssociated::LEN);
+        support_mint.try_serialize(&mut data).unwrap();
+        assert_eq!(data.len(), SupportMintAssociated::LEN);
+
+        let unpacked = SupportMintAssociated::try_deserialize_compat(&data).unwrap();
+        assert_eq!(unpacked.mint, support_mint.mint);
+        assert_eq!(unpacked.support_mint_flags, 1);
+        assert!(!unpacked.get_flag_by_bit(SupportMintFlagBitIndex::Disable));
+    }
 }
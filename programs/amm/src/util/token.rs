@@ -16,8 +16,10 @@ use anchor_spl::token_interface::{initialize_mint2, InitializeMint2, Mint, Token
 use spl_token_2022::{
     self,
     extension::{
+        default_account_state::DefaultAccountState,
         metadata_pointer,
         transfer_fee::{TransferFeeConfig, MAX_FEE_BASIS_POINTS},
+        transfer_hook::TransferHook,
         BaseStateWithExtensions, ExtensionType, StateWithExtensions,
     },
 };
@@ -255,10 +257,11 @@ pub fn support_mint_associated_is_initialized(
         {
             continue;
         }
-        let mint_associated = SupportMintAssociated::try_deserialize(
-            &mut mint_associated_info.data.borrow().as_ref(),
-        )?;
-        if mint_associated.mint == token_mint.key() {
+        let mint_associated =
+            SupportMintAssociated::try_deserialize_compat(&mint_associated_info.data.borrow())?;
+        if mint_associated.mint == token_mint.key()
+            && mint_associated.get_flag_by_bit(SupportMintFlagBitIndex::Disable)
+        {
             mint_associated_is_initialized = true;
             break;
         }
@@ -297,6 +300,50 @@ pub fn is_supported_mint(
     Ok(true)
 }
 
+/// Check the extensions of a token22 mint before registering it as a support mint.
+/// Allowed extensions:
+/// * `TransferFeeConfig`, `InterestBearingConfig`, `ScaledUiAmount`
+/// * `MetadataPointer`, `TokenMetadata`, `GroupPointer`, `TokenGroup`, `GroupMemberPointer`, `TokenGroupMember`
+/// * `MintCloseAuthority`, `PermanentDelegate`, `ConfidentialTransferMint`, `ConfidentialTransferFeeConfig`
+/// * `DefaultAccountState`, only if new accounts are not frozen
+/// * `TransferHook`, only if no hook program is set, pool transfers don't invoke hook programs
+///
+/// Any other extension, e.g. `NonTransferable` or `Pausable`, is rejected.
+pub fn check_support_mint_extensions(mint_info: &AccountInfo) -> Result<()> {
+    let mint_data = mint_info.try_borrow_data()?;
+    let mint = StateWithExtensions::<spl_token_2022::state::Mint>::unpack(&mint_data)?;
+    for e in mint.get_extension_types()? {
+        let allowed = match e {
+            ExtensionType::TransferFeeConfig
+            | ExtensionType::InterestBearingConfig
+            | ExtensionType::ScaledUiAmount
+            | ExtensionType::MetadataPointer
+            | ExtensionType::TokenMetadata
+            | ExtensionType::GroupPointer
+            | ExtensionType::TokenGroup
+            | ExtensionType::GroupMemberPointer
+            | ExtensionType::TokenGroupMember
+            | ExtensionType::MintCloseAuthority
+            | ExtensionType::PermanentDelegate
+            | ExtensionType::ConfidentialTransferMint
+            | ExtensionType::ConfidentialTransferFeeConfig => true,
+            ExtensionType::DefaultAccountState => {
+                mint.get_extension::<DefaultAccountState>()?.state
+                    == spl_token_2022::state::AccountState::Initialized as u8
+            }
+            ExtensionType::TransferHook => {
+                Option::<Pubkey>::from(mint.get_extension::<TransferHook>()?.program_id).is_none()
+            }
+            _ => false,
+        };
+        if !allowed {
+            msg!("not allowed mint extension: {:?}", e);
+            return err!(ErrorCode::NotAllowedMintExtension);
+        }
+    }
+    Ok(())
+}
+
 pub fn create_position_nft_mint_with_extensions<'info>(
     payer: &Signer<'info>,
     position_nft_mint: &AccountInfo<'info>,